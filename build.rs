@@ -6,17 +6,19 @@ use std::path::Path;
 
 pub fn main() {
     let out_dir = env::var_os("OUT_DIR").unwrap();
-    let src_dir = Path::new("src/bin");
     let dst_dir = Path::new(&out_dir);
 
     for &(src, dst) in [
-        ("adslbystat.rs.in", "adslbystat.rs"),
-        ("lostfilm-check.rs.in", "lostfilm-check.rs"),
-        ("trans-done-pb.rs.in", "trans-done-pb.rs"),
-        ("vimb-queue-pocket.rs.in", "vimb-queue-pocket.rs"),
-        ("yaddns.rs.in", "yaddns.rs"),
+        ("src/dns.rs.in", "dns.rs"),
+        ("src/ipsource.rs.in", "ipsource.rs"),
+        ("src/notify/mod.rs.in", "notify.rs"),
+        ("src/notify/pushbullet.rs.in", "notify-pushbullet.rs"),
+        ("src/bin/adslbystat.rs.in", "adslbystat.rs"),
+        ("src/bin/lostfilm-check.rs.in", "lostfilm-check.rs"),
+        ("src/bin/vimb-queue-pocket.rs.in", "vimb-queue-pocket.rs"),
+        ("src/bin/yaddns.rs.in", "yaddns.rs"),
     ].iter() {
-        let (src, dst) = (src_dir.join(src), dst_dir.join(dst));
+        let (src, dst) = (Path::new(src), dst_dir.join(dst));
 
         let mut registry = syntex::Registry::new();
         serde_codegen::register(&mut registry);
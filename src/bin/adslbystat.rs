@@ -7,31 +7,30 @@ extern crate toml;
 extern crate hyper;
 extern crate url;
 extern crate serde;
+extern crate serde_json;
 extern crate regex;
+extern crate time;
 extern crate script_utils as utils;
 
 use regex::Regex;
 use hyper::client::Client;
 use hyper::header::{Authorization, Referer, Basic};
-use hyper::error::Result;
 use encoding::{Encoding, DecoderTrap};
 use encoding::all::WINDOWS_1251;
 use std::process::exit;
 use std::env;
+use std::cmp;
+use std::collections::BTreeMap;
 use std::fmt;
-use std::io::Read;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use serde::Serialize;
+use utils::notify::{self, Template};
 
 #[cfg(test)]
 use test::Bencher;
 
-#[derive(Debug)]
-struct AcctInfo {
-    enabled: bool,
-    account: f32,
-    days: i32,
-    price: i32,
-    credit: Option<i32>,
-}
+include!(concat!(env!("OUT_DIR"), "/adslbystat.rs"));
 
 impl fmt::Display for AcctInfo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -46,116 +45,1323 @@ impl fmt::Display for AcctInfo {
     }
 }
 
-include!(concat!(env!("OUT_DIR"), "/adslbystat.rs"));
+#[derive(Debug, PartialEq)]
+struct ParseError {
+    page: &'static str,
+    missing: Vec<&'static str>,
+}
 
-fn enable_credit(creds: Creds) -> Result<bool> {
-    let client = Client::new();
-    client.get("https://www.adsl.by/credit.js?credit=on")
-          .header(Authorization(Basic {
-              username: creds.username,
-              password: Some(creds.password),
-          }))
-          .header(Referer("https://www.adsl.by".into()))
-          .send()
-          .and_then(|mut resp| {
-              let mut buf = String::new();
-              resp.read_to_string(&mut buf)
-                  .map_err(::hyper::error::Error::Io)
-                  .map(|_| buf.contains("stat: 'Включен'"))
-          })
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "can't find {} on the {} page", self.missing.join(", "), self.page)
+    }
 }
 
-const EXIT_ENABLED: i32 = 0;
-const EXIT_DISABLED: i32 = 1;
-const EXIT_ERROR: i32 = 2;
+fn finish_cell(cell: &mut Option<String>, row: &mut Vec<String>) {
+    if let Some(text) = cell.take() {
+        let text = text.replace("&nbsp;", " ")
+                       .replace("&lt;", "<")
+                       .replace("&gt;", ">")
+                       .replace("&quot;", "\"")
+                       .replace("&amp;", "&");
+        row.push(text.split_whitespace().collect::<Vec<_>>().join(" "));
+    }
+}
 
-fn main() {
-    let state_re = Regex::new(r">Аккаунт</td>\s*<td class='right'><b>Включен<").unwrap();
-    let account_re = Regex::new(r"Осталось трафика на сумму</td>\s*<td class='right'><b>(-?[0-9. ]+)").unwrap();
-    let days_re = Regex::new(r"осталось <b>(-?\d+) д").unwrap();
-    let price_re = Regex::new(r"тариф</td>\s*<td class='right'><b>(\d+) ").unwrap();
-    let credit_re = Regex::new(r"кредит</td>\s*<td class='right'><b>(\d+)%").unwrap();
-
-    let config: Creds = match utils::load_config("adslby/creds.toml") {
-        Some(conf) => conf,
+/// Splits HTML tables into rows of cell texts,
+/// with tags stripped and whitespace collapsed.
+fn table_rows(html: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell: Option<String> = None;
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        if let Some(ref mut text) = cell {
+            text.push_str(&rest[..start]);
+        }
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => break,
+        };
+
+        let tag = rest[start + 1..end].trim().to_lowercase();
+        let closing = tag.starts_with('/');
+        let name: String = tag.trim_left_matches('/').chars().take_while(|c| c.is_alphanumeric()).collect();
+        match (closing, &*name) {
+            (_, "tr") | (true, "table") => {
+                finish_cell(&mut cell, &mut row);
+                if !row.is_empty() {
+                    rows.push(row);
+                    row = Vec::new();
+                }
+            }
+            (false, "td") | (false, "th") => {
+                finish_cell(&mut cell, &mut row);
+                cell = Some(String::new());
+            }
+            (true, "td") | (true, "th") => finish_cell(&mut cell, &mut row),
+            (false, "br") => {
+                if let Some(ref mut text) = cell {
+                    text.push(' ');
+                }
+            }
+            _ => (),
+        }
+        rest = &rest[end + 1..];
+    }
+
+    finish_cell(&mut cell, &mut row);
+    if !row.is_empty() {
+        rows.push(row);
+    }
+    rows
+}
+
+/// Parses a number at the start of the text, like `12 345.67 руб.`
+fn leading_number<T: FromStr>(text: &str) -> Option<T> {
+    let number: String = text.trim_left()
+                             .chars()
+                             .take_while(|c| c.is_digit(10) || c.is_whitespace() || *c == '-' || *c == '.' || *c == ',')
+                             .filter(|c| !c.is_whitespace())
+                             .collect();
+    number.replace(',', ".").parse().ok()
+}
+
+/// Maps label/value cells of the account page to `AcctInfo` fields.
+fn parse_acct(html: &str) -> ::std::result::Result<AcctInfo, ParseError> {
+    let days_re = Regex::new(r"осталось\s+(-?\d+)\s*д").unwrap();
+    let (mut enabled, mut account, mut days, mut price, mut credit) = (None, None, None, None, None);
+
+    for row in table_rows(html) {
+        // days left are mentioned in the text of a value cell
+        for cell in &row {
+            let text = cell.to_lowercase();
+            if let Some(value) = days_re.captures(&*text).and_then(|c| c.at(1).and_then(|v| v.parse().ok())) {
+                days = Some(value);
+            }
+        }
+
+        if row.len() < 2 {
+            continue;
+        }
+        let (label, value) = (row[0].to_lowercase(), &*row[1]);
+        if label == "аккаунт" {
+            enabled = Some(value.starts_with("Включен"));
+        } else if label.ends_with("осталось трафика на сумму") {
+            account = leading_number(value);
+        } else if label.ends_with("тариф") {
+            price = leading_number(value);
+        } else if label.ends_with("кредит") {
+            credit = leading_number(value);
+        }
+    }
+
+    match (enabled, account, days, price) {
+        (Some(enabled), Some(account), Some(days), Some(price)) => {
+            Ok(AcctInfo {
+                enabled: enabled,
+                account: account,
+                days: days,
+                price: price,
+                credit: credit,
+            })
+        }
+        _ => {
+            let missing = [("enabled", enabled.is_none()),
+                           ("account", account.is_none()),
+                           ("days", days.is_none()),
+                           ("price", price.is_none())];
+            Err(ParseError {
+                page: "account",
+                missing: missing.iter().filter(|&&(_, none)| none).map(|&(name, _)| name).collect(),
+            })
+        }
+    }
+}
+
+/// Converts `25.05.2016 13:45` or `2016-05-25` to ISO date and optional time.
+fn parse_date(text: &str) -> Option<(String, Option<String>)> {
+    let mut words = text.split_whitespace();
+    let date = match words.next() {
+        Some(date) => date,
+        None => return None,
+    };
+    let time = words.next().map(|t| t.to_owned());
+    let all_digits = |parts: &[&str], lens: &[usize]| {
+        parts.len() == lens.len() &&
+        parts.iter().zip(lens).all(|(part, &len)| part.len() == len && part.chars().all(|c| c.is_digit(10)))
+    };
+
+    let dotted: Vec<&str> = date.split('.').collect();
+    let dashed: Vec<&str> = date.split('-').collect();
+    if all_digits(&dotted, &[2, 2, 4]) {
+        Some((format!("{}-{}-{}", dotted[2], dotted[1], dotted[0]), time))
+    } else if all_digits(&dashed, &[4, 2, 2]) {
+        Some((date.to_owned(), time))
+    } else {
+        None
+    }
+}
+
+/// Maps columns of the traffic table by header labels, then reads
+/// the rows starting with a date. Totals and other rows are skipped.
+fn parse_traffic(html: &str) -> ::std::result::Result<Vec<TrafficRecord>, ParseError> {
+    let columns: [(&'static str, &'static [&'static str]); 4] = [("date", &["дата"]),
+                                                                 ("in", &["вход"]),
+                                                                 ("out", &["исход"]),
+                                                                 ("cost", &["стоимость", "сумма"])];
+    let find = |row: &[String], labels: &[&str]| {
+        row.iter().position(|cell| {
+            let cell = cell.to_lowercase();
+            labels.iter().any(|label| cell.contains(label))
+        })
+    };
+
+    let rows = table_rows(html);
+    let header = rows.iter().enumerate().filter_map(|(i, row)| {
+        let found: Vec<Option<usize>> = columns.iter().map(|&(_, labels)| find(row, labels)).collect();
+        match (found[0], found[1], found[2], found[3]) {
+            (Some(date), Some(mb_in), Some(mb_out), Some(cost)) => Some((i, date, mb_in, mb_out, cost)),
+            _ => None,
+        }
+    }).next();
+
+    let (start, date, mb_in, mb_out, cost) = match header {
+        Some(header) => header,
         None => {
-            println!("Config file load error.");
-            exit(EXIT_ERROR);
+            return Err(ParseError {
+                page: "traffic",
+                missing: columns.iter()
+                                .filter(|&&(_, labels)| rows.iter().all(|row| find(row, labels).is_none()))
+                                .map(|&(name, _)| name)
+                                .collect(),
+            })
         }
     };
 
+    Ok(rows[start + 1..]
+           .iter()
+           .filter_map(|row| {
+               let last = cmp::max(cmp::max(date, mb_in), cmp::max(mb_out, cost));
+               if row.len() <= last {
+                   return None;
+               }
+               parse_date(&*row[date]).map(|(day, time)| {
+                   TrafficRecord {
+                       date: day,
+                       time: time,
+                       mb_in: leading_number(&*row[mb_in]).unwrap_or(0.0),
+                       mb_out: leading_number(&*row[mb_out]).unwrap_or(0.0),
+                       cost: leading_number(&*row[cost]).unwrap_or(0.0),
+                   }
+               })
+           })
+           .collect())
+}
+
+/// Sums sessions into one record per day.
+fn traffic_by_day(records: Vec<TrafficRecord>) -> Vec<TrafficRecord> {
+    let mut days: Vec<TrafficRecord> = Vec::new();
+    for record in records {
+        if let Some(day) = days.iter_mut().find(|day| day.date == record.date) {
+            day.mb_in += record.mb_in;
+            day.mb_out += record.mb_out;
+            day.cost += record.cost;
+            continue;
+        }
+        days.push(TrafficRecord { time: None, ..record });
+    }
+    days
+}
+
+/// Fetches traffic records of the account within the inclusive date range,
+/// summed by day if `daily`.
+fn account_traffic(creds: &Creds,
+                   range: &[Option<String>],
+                   daily: bool)
+                   -> ::std::result::Result<Vec<TrafficRecord>, String> {
+    let url = creds.traffic_url.clone().unwrap_or_else(|| creds.url("/002.htm"));
+    let records = try!(fetch_page(creds, &*url).and_then(|page| parse_traffic(&*page).map_err(|err| err.to_string())));
+
+    let records: Vec<TrafficRecord> = records.into_iter()
+                                             .filter(|r| range[0].as_ref().map(|from| r.date >= *from).unwrap_or(true))
+                                             .filter(|r| range[1].as_ref().map(|to| r.date <= *to).unwrap_or(true))
+                                             .collect();
+    Ok(if daily {
+        traffic_by_day(records)
+    } else {
+        records
+    })
+}
+
+/// Prints traffic records between `--from` and `--to` dates (inclusive),
+/// sessions are summed by day with `--daily`. Returns exit code.
+fn traffic(accounts: &[Creds], args: &[String], format: &str) -> i32 {
+    let mut range = Vec::new();
+    for name in &["--from", "--to"] {
+        match arg_value(args, name).map(|value| parse_date(&*value)) {
+            Some(Some((date, _))) => range.push(Some(date)),
+            Some(None) => {
+                log(&format!("Invalid {} date, expected DD.MM.YYYY or YYYY-MM-DD", name));
+                return utils::config::EXIT_USAGE;
+            }
+            None => range.push(None),
+        }
+    }
+    if format != "text" && format != "json" {
+        log("Traffic can be printed only as text or json");
+        return utils::config::EXIT_USAGE;
+    }
+
+    let daily = args.iter().any(|arg| arg == "--daily");
+    let mut code = 0;
+    let mut results = Vec::new();
+    for creds in accounts {
+        match account_traffic(creds, &range, daily) {
+            Ok(records) => results.push((creds.name.clone(), records)),
+            Err(err) => {
+                log(&format!("{}Error getting traffic stats: {}", creds.prefix(), err));
+                code = EXIT_ERROR;
+            }
+        }
+    }
+    let named = accounts.iter().any(|creds| creds.name.is_some());
+
+    if format == "json" {
+        if named {
+            let by_name: BTreeMap<String, Vec<TrafficRecord>> =
+                results.into_iter().map(|(name, records)| (name.unwrap_or_else(String::new), records)).collect();
+            println!("{}", serde_json::to_string_pretty(&by_name).unwrap());
+        } else if let Some(&(_, ref records)) = results.first() {
+            println!("{}", serde_json::to_string_pretty(records).unwrap());
+        }
+    } else if args.iter().any(|arg| arg == "--csv") {
+        println!("{}date,time,mb_in,mb_out,cost", if named { "account," } else { "" });
+        for &(ref name, ref records) in &results {
+            for r in records {
+                let mut fields: Vec<String> = name.iter().cloned().collect();
+                fields.extend(vec![r.date.clone(),
+                                   r.time.clone().unwrap_or_else(String::new),
+                                   format!("{:.2}", r.mb_in),
+                                   format!("{:.2}", r.mb_out),
+                                   format!("{:.2}", r.cost)]);
+                println!("{}", utils::csv::row(&fields));
+            }
+        }
+    } else {
+        for &(ref name, ref records) in &results {
+            if let Some(ref name) = *name {
+                println!("[{}]", name);
+            }
+            println!("{:<18}{:>12}{:>12}{:>12}", "Date", "In, MiB", "Out, MiB", "Cost, rub");
+            let (mut mb_in, mut mb_out, mut cost) = (0.0, 0.0, 0.0);
+            for r in records {
+                let when = match r.time {
+                    Some(ref time) => format!("{} {}", r.date, time),
+                    None => r.date.clone(),
+                };
+                println!("{:<18}{:>12.2}{:>12.2}{:>12.2}", when, r.mb_in, r.mb_out, r.cost);
+                mb_in += r.mb_in;
+                mb_out += r.mb_out;
+                cost += r.cost;
+            }
+            println!("{:<18}{:>12.2}{:>12.2}{:>12.2}", "Total", mb_in, mb_out, cost);
+            if named {
+                println!("");
+            }
+        }
+    }
+
+    code
+}
+
+fn gauges(acct: &AcctInfo) -> Vec<(&'static str, &'static str, String)> {
+    let mut gauges = vec![("adsl_account_balance_rub", "Money left on the account, rub", acct.account.to_string()),
+                          ("adsl_days_left", "Days left until the account runs out", acct.days.to_string()),
+                          ("adsl_price_per_mib", "Traffic price per MiB, rub", acct.price.to_string())];
+    if let Some(credit) = acct.credit {
+        gauges.push(("adsl_credit_percent", "Allowed credit, percent", credit.to_string()));
+    }
+    gauges.push(("adsl_enabled",
+                 "Whether the account is enabled",
+                 if acct.enabled { "1" } else { "0" }.to_owned()));
+    gauges
+}
+
+/// Writes gauges in Prometheus text format, for node_exporter textfile collector.
+/// Named accounts are told apart by `account` label.
+fn prometheus(accts: &[(Option<String>, AcctInfo)]) -> String {
+    let mut metrics: Vec<(&'static str, &'static str, Vec<String>)> = Vec::new();
+    for &(ref name, ref acct) in accts {
+        let labels = match *name {
+            Some(ref name) => format!("{{account=\"{}\"}}", name.replace("\\", "\\\\").replace("\"", "\\\"")),
+            None => String::new(),
+        };
+        for (metric, help, value) in gauges(acct) {
+            let sample = format!("{}{} {}\n", metric, labels, value);
+            let index = metrics.iter().position(|&(known, _, _)| known == metric);
+            match index {
+                Some(i) => metrics[i].2.push(sample),
+                None => metrics.push((metric, help, vec![sample])),
+            }
+        }
+    }
+
+    metrics.iter()
+           .map(|&(metric, help, ref samples)| format!("# HELP {0} {1}\n# TYPE {0} gauge\n{2}", metric, help, samples.concat()))
+           .collect()
+}
+
+fn to_toml<T: Serialize>(value: &T) -> String {
+    let mut encoder = toml::Encoder::new();
+    value.serialize(&mut encoder).unwrap();
+    toml::Value::Table(encoder.toml).to_string()
+}
+
+/// Diagnostics go to stderr, so that stdout has only the requested output
+/// and stays parseable in json, toml and prometheus formats.
+fn log(msg: &str) {
+    let _ = writeln!(io::stderr(), "{}", msg);
+}
+
+const FORMATS: [&'static str; 4] = ["text", "json", "toml", "prometheus"];
+
+/// Prints account stats. A single unnamed account is printed as is,
+/// named accounts are keyed (or headed in text) by their names.
+fn print_accts(accts: &[(Option<String>, AcctInfo)], format: &str) {
+    let single = if accts.len() == 1 && accts[0].0.is_none() {
+        Some(&accts[0].1)
+    } else {
+        None
+    };
+    let by_name: BTreeMap<&str, &AcctInfo> = accts.iter()
+                                                  .map(|&(ref name, ref acct)| (name.as_ref().map(|n| &**n).unwrap_or(""), acct))
+                                                  .collect();
+
+    match format {
+        "json" => match single {
+            Some(acct) => println!("{}", serde_json::to_string_pretty(acct).unwrap()),
+            None => println!("{}", serde_json::to_string_pretty(&by_name).unwrap()),
+        },
+        "toml" => match single {
+            Some(acct) => print!("{}", to_toml(acct)),
+            None => print!("{}", to_toml(&by_name)),
+        },
+        "prometheus" => print!("{}", prometheus(accts)),
+        _ => {
+            for &(ref name, ref acct) in accts {
+                match *name {
+                    Some(ref name) => println!("[{}]\n{}\n", name, acct),
+                    None => println!("{}", acct),
+                }
+            }
+        }
+    }
+}
+
+/// Options which take a value, as `--name <value>` or `--name=<value>`.
+const VALUE_OPTIONS: [&'static str; 4] = ["--format", "--account", "--from", "--to"];
+
+/// The first positional argument, skipping options and their values.
+fn subcommand(args: &[String]) -> Option<&str> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if VALUE_OPTIONS.contains(&&**arg) {
+            args.next();
+        } else if !arg.starts_with("--") {
+            return Some(arg);
+        }
+    }
+    None
+}
+
+/// Option value from `--name <value>` or `--name=<value>` argument.
+fn arg_value(args: &[String], name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    args.iter()
+        .enumerate()
+        .filter_map(|(i, arg)| {
+            if arg == name {
+                args.get(i + 1).cloned()
+            } else if arg.starts_with(&*prefix) {
+                Some(arg[prefix.len()..].to_owned())
+            } else {
+                None
+            }
+        })
+        .next()
+}
+
+/// Alerts which hold for the account, with names to track them by.
+fn active_alerts(acct: &AcctInfo, alerts: &AlertsConfig) -> Vec<(&'static str, String)> {
+    let mut active = Vec::new();
+    if let Some(min_account) = alerts.min_account {
+        if acct.account < min_account {
+            active.push(("account",
+                         format!("Account balance is {:.2} rub, less than {:.2} rub", acct.account, min_account)));
+        }
+    }
+    if let Some(min_days) = alerts.min_days {
+        if acct.days < min_days {
+            active.push(("days", format!("{} days left, less than {}", acct.days, min_days)));
+        }
+    }
+    active
+}
+
+const ALERTS_STATE: &'static str = "adslby/alerts";
+
+/// Notifies about alerts which were not sent yet. An alert is sent again
+/// only after its condition clears and comes back.
+fn send_alerts(creds: &Creds, acct: &AcctInfo, alerts: &AlertsConfig) {
+    let state = creds.state(ALERTS_STATE);
+    let sent = utils::state::read_state(&*state).unwrap_or_else(String::new);
+    let sent: Vec<&str> = sent.lines().collect();
+    let account = format!("{:.2}", acct.account);
+    let days = acct.days.to_string();
+
+    let mut still_sent = Vec::new();
+    for (name, text) in active_alerts(acct, alerts) {
+        if sent.contains(&name) {
+            still_sent.push(name);
+            continue;
+        }
+
+        let text = format!("{}{}", creds.prefix(), text);
+        match notify::send_event("low_balance",
+                                 Template::new("ADSL.by account alert", "{alert}"),
+                                 &[("alert", &*text), ("account", &*account), ("days", &*days)]) {
+            Ok(receipt) => {
+                log(&format!("Alert sent with {}: {}", receipt, text));
+                still_sent.push(name);
+            }
+            // not marked as sent, so it's retried next run
+            Err(err) => log(&format!("Error sending alert: {}", err)),
+        }
+    }
+
+    if let Err(err) = utils::state::write_state(&*state, &*still_sent.join("\n")) {
+        log(&format!("Error saving alerts state: {}", err));
+    }
+}
+
+/// Outcome of a credit request, as reported by the server.
+#[derive(Debug, PartialEq)]
+struct CreditResult {
+    enabled: bool,
+    status: Option<String>,
+}
+
+impl fmt::Display for CreditResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "credit was {}", if self.enabled { "enabled" } else { "not enabled" }));
+        if let Some(ref status) = self.status {
+            try!(write!(f, ", account status: {}", status));
+        }
+        Ok(())
+    }
+}
+
+/// Parses the script returned by `credit.js`, like `stat: 'Включен'`.
+fn parse_credit_response(body: &str) -> CreditResult {
+    let status_re = Regex::new(r"stat:\s*'([^']*)'").unwrap();
+    let status = status_re.captures(body).and_then(|c| c.at(1)).map(|s| s.to_owned());
+    CreditResult {
+        enabled: status.as_ref().map(|s| s == "Включен").unwrap_or(false),
+        status: status,
+    }
+}
+
+/// Fetches a page with Basic auth and decodes it from WINDOWS_1251.
+fn fetch_page(creds: &Creds, url: &str) -> ::std::result::Result<String, String> {
     let client = Client::new();
     // client.set_ssl_verifier(Box::new(utils::permissive_ssl_checker));
 
-    let buf = match client.get("https://www.adsl.by/001.htm")
-                          .header(Authorization(Basic {
-                              username: config.username.clone(),
-                              password: Some(config.password.clone()),
-                          }))
-                          .send()
-                          .and_then(|mut resp| {
-                              let mut buf = Vec::new();
-                              resp.read_to_end(&mut buf)
-                                  .map(|_| buf)
-                                  .map_err(::hyper::error::Error::Io)
-                          }) {
-        Ok(buf) => buf,
-        Err(err) => {
-            println!("Error requesting account stats: {}", err);
-            exit(EXIT_ERROR);
+    let mut resp = try!(client.get(url)
+                              .header(Authorization(Basic {
+                                  username: creds.username.clone(),
+                                  password: Some(creds.password.clone()),
+                              }))
+                              .header(Referer(creds.url("/")))
+                              .send()
+                              .map_err(|err| err.to_string()));
+    if !resp.status.is_success() {
+        return Err(format!("HTTP status {}", resp.status));
+    }
+
+    let mut buf = Vec::new();
+    try!(resp.read_to_end(&mut buf).map_err(|err| err.to_string()));
+    WINDOWS_1251.decode(&*buf, DecoderTrap::Replace).map_err(|err| format!("can't decode HTML page: {}", err))
+}
+
+fn enable_credit(creds: &Creds) -> ::std::result::Result<CreditResult, String> {
+    fetch_page(creds, &*creds.url("/credit.js?credit=on")).map(|body| parse_credit_response(&*body))
+}
+
+const CREDIT_STATE: &'static str = "adslby/credit_enabled";
+
+/// Why credit should be enabled according to the policy, `None` if it's not needed.
+fn credit_reason(acct: &AcctInfo, policy: &CreditPolicy) -> Option<String> {
+    if acct.account < 0.0 {
+        Some(format!("balance is negative ({:.2} rub)", acct.account))
+    } else if policy.min_days.map(|min_days| acct.days < min_days).unwrap_or(false) {
+        Some(format!("{} days left", acct.days))
+    } else {
+        None
+    }
+}
+
+/// Checks credit limits: the account must allow credit within `max_percent`,
+/// and credit must not have been enabled in the last `interval` hours.
+fn check_credit_limits(acct: &AcctInfo,
+                       policy: &CreditPolicy,
+                       last_enabled: Option<i64>,
+                       now: i64)
+                       -> ::std::result::Result<(), String> {
+    match acct.credit {
+        None | Some(0) => return Err("no credit is allowed for the account".to_owned()),
+        Some(percent) if policy.max_percent.map(|max| percent > max).unwrap_or(false) => {
+            return Err(format!("allowed credit {}% exceeds the {}% limit",
+                               percent,
+                               policy.max_percent.unwrap()))
         }
-    };
+        _ => (),
+    }
 
-    let cont = match WINDOWS_1251.decode(&*buf, DecoderTrap::Replace) {
-        Ok(res) => res,
-        Err(err) => {
-            println!("Error decoding HTML page: {}", err);
-            exit(EXIT_ERROR);
+    let interval = policy.interval.unwrap_or(24) * 3600;
+    match last_enabled {
+        Some(last) if now - last < interval => {
+            Err(format!("credit was already enabled {} hours ago", (now - last) / 3600))
         }
+        _ => Ok(()),
+    }
+}
+
+/// Enables credit, records the time and notifies about it.
+/// Returns whether the account is enabled after that.
+fn apply_credit(creds: &Creds, reason: &str) -> ::std::result::Result<bool, String> {
+    let result = try!(enable_credit(creds));
+    log(&format!("{}Credit requested because {}: {}.", creds.prefix(), reason, result));
+
+    if result.enabled {
+        let now = time::get_time().sec.to_string();
+        if let Err(err) = utils::state::write_state(&*creds.state(CREDIT_STATE), &*now) {
+            log(&format!("Error saving credit state: {}", err));
+        }
+    }
+
+    let message = result.to_string();
+    let reason = format!("{}{}", creds.prefix(), reason);
+    if let Err(err) = notify::send_event("credit",
+                                         Template::new("ADSL.by credit", "{reason}: {result}"),
+                                         &[("reason", &*reason), ("result", &*message)]) {
+        log(&format!("Error sending notification: {}", err));
+    }
+
+    Ok(result.enabled)
+}
+
+const HISTORY_STATE: &'static str = "adslby/history";
+
+/// Account state at some moment, as stored in the history file.
+#[derive(Debug, Clone)]
+struct Reading {
+    time: i64,
+    acct: AcctInfo,
+}
+
+/// Appends a reading to the history, one tab separated line:
+/// unix time, account, days, price, credit (empty if not allowed), enabled.
+fn record_reading(state: &str, acct: &AcctInfo, now: i64) {
+    let line = format!("{}\t{}\t{}\t{}\t{}\t{}",
+                       now,
+                       acct.account,
+                       acct.days,
+                       acct.price,
+                       acct.credit.map(|c| c.to_string()).unwrap_or_else(String::new),
+                       if acct.enabled { 1 } else { 0 });
+    if let Err(err) = utils::state::append_state(state, &*line) {
+        log(&format!("Error saving history: {}", err));
+    }
+}
+
+fn parse_reading(line: &str) -> Option<Reading> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 6 {
+        return None;
+    }
+    match (fields[0].parse(), fields[1].parse(), fields[2].parse(), fields[3].parse()) {
+        (Ok(time), Ok(account), Ok(days), Ok(price)) => {
+            Some(Reading {
+                time: time,
+                acct: AcctInfo {
+                    enabled: fields[5] == "1",
+                    account: account,
+                    days: days,
+                    price: price,
+                    credit: fields[4].parse().ok(),
+                },
+            })
+        }
+        _ => None,
+    }
+}
+
+fn read_history(state: &str) -> Vec<Reading> {
+    let mut readings: Vec<Reading> = utils::state::read_state(state)
+                                         .unwrap_or_else(String::new)
+                                         .lines()
+                                         .filter_map(parse_reading)
+                                         .collect();
+    readings.sort_by(|a, b| a.time.cmp(&b.time));
+    readings
+}
+
+/// Money spent and topped up over a period, with the balance at its end.
+#[derive(Debug, PartialEq)]
+struct Summary {
+    period: String,
+    spent: f32,
+    topped_up: f32,
+    balance: f32,
+}
+
+/// Sums balance changes between consecutive readings by period, balance
+/// decreases are counted as spending and increases as top ups.
+fn summarize<F: Fn(i64) -> String>(readings: &[Reading], period: F) -> Vec<Summary> {
+    let mut summaries: Vec<Summary> = Vec::new();
+    let mut previous: Option<f32> = None;
+
+    for reading in readings {
+        let name = period(reading.time);
+        let delta = previous.map(|prev| reading.acct.account - prev).unwrap_or(0.0);
+        previous = Some(reading.acct.account);
+
+        if summaries.last().map(|last| last.period != name).unwrap_or(true) {
+            summaries.push(Summary {
+                period: name,
+                spent: 0.0,
+                topped_up: 0.0,
+                balance: 0.0,
+            });
+        }
+        if let Some(last) = summaries.last_mut() {
+            if delta < 0.0 {
+                last.spent -= delta;
+            } else {
+                last.topped_up += delta;
+            }
+            last.balance = reading.acct.account;
+        }
+    }
+
+    summaries
+}
+
+const BURN_RATE_DAYS: i64 = 7;
+
+/// Average spending in rub per day over the last week of readings.
+fn burn_rate(readings: &[Reading]) -> Option<f32> {
+    let last = match readings.last() {
+        Some(last) => last.time,
+        None => return None,
     };
+    let recent: Vec<&Reading> = readings.iter()
+                                        .filter(|r| last - r.time <= BURN_RATE_DAYS * 86400)
+                                        .collect();
+    let span = (last - recent[0].time) as f32 / 86400.0;
+    if span <= 0.0 {
+        return None;
+    }
 
-    let acct = AcctInfo {
-        enabled: state_re.is_match(&*cont),
-        account: account_re.captures(&*cont)
-                           .and_then(|c| c.at(1).and_then(|v| v.replace(" ", "").parse().ok()))
-                           .unwrap_or(0f32),
-        days: days_re.captures(&*cont)
-                     .and_then(|c| c.at(1).and_then(|v| v.parse().ok()))
-                     .unwrap_or(0),
-        price: price_re.captures(&*cont)
-                       .and_then(|c| c.at(1).and_then(|v| v.parse().ok()))
-                       .unwrap_or(0),
-        credit: credit_re.captures(&*cont).and_then(|c| c.at(1).and_then(|v| v.parse().ok())),
-    };
-
-    println!("{}", acct);
-
-    exit(if acct.enabled {
-        EXIT_ENABLED
+    let spent: f32 = recent.windows(2)
+                           .map(|pair| pair[0].acct.account - pair[1].acct.account)
+                           .filter(|&delta| delta > 0.0)
+                           .fold(0.0, |sum, delta| sum + delta);
+    Some(spent / span)
+}
+
+/// How many days the forecast may differ from the ISP's estimate,
+/// as the ISP computes it from its own averages.
+const DAYS_TOLERANCE: f32 = 3.0;
+
+/// Difference between the forecast and the ISP's days left,
+/// if it's beyond the tolerance.
+fn days_mismatch(forecast: f32, isp_days: i32) -> Option<f32> {
+    let diff = forecast - isp_days as f32;
+    if diff.abs() > DAYS_TOLERANCE {
+        Some(diff)
     } else {
-        if env::args().position(|arg| arg == "credit").is_some() {
-            match enable_credit(config) {
-                Ok(true) => {
-                    println!("Credit was enabled.");
-                    EXIT_ENABLED
+        None
+    }
+}
+
+fn local_date(time: i64, format: &str) -> String {
+    time::at(time::Timespec::new(time, 0)).strftime(format).map(|t| t.to_string()).unwrap_or_else(|_| String::new())
+}
+
+/// Prints daily and monthly spending of each account with the depletion
+/// forecast, or one of the summaries as CSV.
+fn report(accounts: &[Creds], csv: bool, monthly: bool) {
+    let named = accounts.iter().any(|creds| creds.name.is_some());
+    if csv {
+        println!("{}period,spent,topped_up,balance", if named { "account," } else { "" });
+    }
+
+    for creds in accounts {
+        let readings = read_history(&*creds.state(HISTORY_STATE));
+        if csv {
+            let period = if monthly { "%Y-%m" } else { "%Y-%m-%d" };
+            for summary in summarize(&readings, |t| local_date(t, period)) {
+                let mut fields: Vec<String> = creds.name.iter().cloned().collect();
+                fields.extend(vec![summary.period,
+                                   format!("{:.2}", summary.spent),
+                                   format!("{:.2}", summary.topped_up),
+                                   format!("{:.2}", summary.balance)]);
+                println!("{}", utils::csv::row(&fields));
+            }
+        } else {
+            if let Some(ref name) = creds.name {
+                println!("[{}]", name);
+            }
+            report_text(&readings);
+            if named {
+                println!("");
+            }
+        }
+    }
+}
+
+fn report_text(readings: &[Reading]) {
+    if readings.is_empty() {
+        println!("No readings recorded yet.");
+        return;
+    }
+
+    let daily = summarize(readings, |t| local_date(t, "%Y-%m-%d"));
+    let months = summarize(readings, |t| local_date(t, "%Y-%m"));
+
+    for &(title, summaries) in [("Date", &daily), ("Month", &months)].iter() {
+        println!("{:<12}{:>12}{:>12}{:>12}", title, "Spent", "Topped up", "Balance");
+        for summary in summaries {
+            println!("{:<12}{:>12.2}{:>12.2}{:>12.2}",
+                     summary.period,
+                     summary.spent,
+                     summary.topped_up,
+                     summary.balance);
+        }
+        println!("");
+    }
+
+    let last = readings.last().unwrap();
+    match burn_rate(readings) {
+        Some(rate) if rate > 0.0 => {
+            let days_left = last.acct.account / rate;
+            println!("Burn rate: {:.2} rub/day", rate);
+            println!("Projected depletion: {} (in {:.0} days, ISP says {} days)",
+                     local_date(last.time + (days_left.max(0.0) * 86400.0) as i64, "%Y-%m-%d"),
+                     days_left,
+                     last.acct.days);
+            if let Some(diff) = days_mismatch(days_left, last.acct.days) {
+                println!("Warning: forecast is {:.0} days {} than the ISP's estimate",
+                         diff.abs(),
+                         if diff > 0.0 { "longer" } else { "shorter" });
+            }
+        }
+        _ => println!("Burn rate: not enough data (ISP says {} days left)", last.acct.days),
+    }
+}
+
+const BASE_URL: &'static str = "https://www.adsl.by";
+
+impl Creds {
+    /// Full URL of a page on the ISP site, `base_url` allows to point it
+    /// to a mirror or a test server.
+    fn url(&self, path: &str) -> String {
+        format!("{}{}",
+                self.base_url.as_ref().map(|url| url.trim_right_matches('/')).unwrap_or(BASE_URL),
+                path)
+    }
+
+    /// State file of the account: named accounts get their own files,
+    /// while a single unnamed one keeps the plain names.
+    fn state(&self, base: &str) -> String {
+        match self.name {
+            Some(ref name) => format!("{}-{}", base, name),
+            None => base.to_owned(),
+        }
+    }
+
+    /// Prefix for messages about the account.
+    fn prefix(&self) -> String {
+        match self.name {
+            Some(ref name) => format!("{}: ", name),
+            None => String::new(),
+        }
+    }
+}
+
+/// Account names become a part of state file names,
+/// so only `[A-Za-z0-9_-]` is allowed in them.
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() &&
+    name.chars().all(|c| (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || (c >= '0' && c <= '9') || c == '-' || c == '_')
+}
+
+/// Accounts to check: `[[account]]` tables, which default to top level
+/// `base_url`, `traffic_url`, `[alerts]` and `[credit]`, or a single unnamed account
+/// from top level `username` and `password`.
+fn accounts(config: Config) -> ::std::result::Result<Vec<Creds>, String> {
+    let Config { username, password, base_url, traffic_url, alerts, credit, account } = config;
+    let list = match account {
+        Some(list) => list,
+        None => {
+            return match (username, password) {
+                (Some(username), Some(password)) => {
+                    Ok(vec![Creds {
+                                name: None,
+                                username: username,
+                                password: password,
+                                base_url: base_url,
+                                traffic_url: traffic_url,
+                                alerts: alerts,
+                                credit: credit,
+                            }])
                 }
-                Ok(false) => {
-                    println!("Credit was not enabled.");
-                    EXIT_DISABLED
+                _ => Err("either username and password or [[account]] tables are required".to_owned()),
+            };
+        }
+    };
+
+    let mut accounts: Vec<Creds> = Vec::new();
+    for mut creds in list {
+        match creds.name {
+            None => return Err("every [[account]] needs a name".to_owned()),
+            Some(ref name) if !valid_name(name) => {
+                return Err(format!("account name {:?} may contain only letters, digits, - and _", name));
+            }
+            Some(ref name) if accounts.iter().any(|other| other.name.as_ref() == Some(name)) => {
+                return Err(format!("account {} is defined twice", name));
+            }
+            _ => {}
+        }
+        if creds.base_url.is_none() {
+            creds.base_url = base_url.clone();
+        }
+        if creds.traffic_url.is_none() {
+            creds.traffic_url = traffic_url.clone();
+        }
+        if creds.alerts.is_none() {
+            creds.alerts = alerts.clone();
+        }
+        if creds.credit.is_none() {
+            creds.credit = credit.clone();
+        }
+        accounts.push(creds);
+    }
+    if accounts.is_empty() {
+        return Err("no accounts in [[account]] tables".to_owned());
+    }
+    Ok(accounts)
+}
+
+fn fetch_acct(creds: &Creds) -> ::std::result::Result<AcctInfo, String> {
+    let page = try!(fetch_page(creds, &*creds.url("/001.htm"))
+                        .map_err(|err| format!("Error requesting account stats: {}", err)));
+    parse_acct(&*page).map_err(|err| format!("Error parsing account stats: {}", err))
+}
+
+const EXIT_ENABLED: i32 = 0;
+const EXIT_DISABLED: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+
+/// Records the reading, sends alerts and enables credit for the account
+/// if needed. Returns exit code for its state.
+fn check_account(creds: &Creds, acct: &AcctInfo, force_credit: bool) -> i32 {
+    record_reading(&*creds.state(HISTORY_STATE), acct, time::get_time().sec);
+
+    if let Some(ref alerts) = creds.alerts {
+        send_alerts(creds, acct, alerts);
+    }
+
+    // `credit` argument enables credit for a disabled account unconditionally,
+    // `[credit]` policy with `auto = true` does it proactively within limits
+    let reason = match creds.credit {
+        _ if !acct.enabled && force_credit => Some("account is disabled".to_owned()),
+        Some(ref policy) if policy.auto.unwrap_or(false) => {
+            credit_reason(acct, policy).and_then(|reason| {
+                let last_enabled = utils::state::read_state(&*creds.state(CREDIT_STATE))
+                                       .and_then(|s| s.trim().parse().ok());
+                match check_credit_limits(acct, policy, last_enabled, time::get_time().sec) {
+                    Ok(()) => Some(reason),
+                    Err(err) => {
+                        log(&format!("{}Credit is needed because {}, but {}.", creds.prefix(), reason, err));
+                        None
+                    }
                 }
+            })
+        }
+        _ => None,
+    };
+
+    let enabled = match reason {
+        Some(reason) => {
+            match apply_credit(creds, &*reason) {
+                Ok(enabled) => acct.enabled || enabled,
                 Err(err) => {
-                    println!("Error enabling credit: {}", err);
-                    EXIT_ERROR
+                    log(&format!("{}Error enabling credit: {}", creds.prefix(), err));
+                    return EXIT_ERROR;
                 }
             }
-        } else {
-            EXIT_DISABLED
         }
-    });
+        None => acct.enabled,
+    };
+
+    if enabled {
+        EXIT_ENABLED
+    } else {
+        EXIT_DISABLED
+    }
+}
+
+fn main() {
+    let args = utils::config::strip_overrides(env::args());
+    let format = arg_value(&args, "--format").unwrap_or_else(|| "text".to_owned());
+    if !FORMATS.contains(&&*format) {
+        log(&format!("Unknown output format {}, expected one of {}", format, FORMATS.join(", ")));
+        exit(utils::config::EXIT_USAGE);
+    }
+
+    let config: Config = utils::load_config_verbose("adslby/creds.toml");
+    let accounts = match accounts(config) {
+        Ok(accounts) => accounts,
+        Err(err) => {
+            log(&format!("Invalid adslby/creds.toml: {}", err));
+            exit(utils::config::EXIT_CONFIG);
+        }
+    };
+    let accounts = match arg_value(&args, "--account") {
+        Some(name) => {
+            let selected: Vec<Creds> = accounts.iter().filter(|creds| creds.name.as_ref() == Some(&name)).cloned().collect();
+            if selected.is_empty() {
+                let names: Vec<String> = accounts.iter().filter_map(|creds| creds.name.clone()).collect();
+                log(&format!("Unknown account {}, expected one of: {}", name, names.join(", ")));
+                exit(utils::config::EXIT_USAGE);
+            }
+            selected
+        }
+        None => accounts,
+    };
+
+    if subcommand(&args) == Some("report") {
+        report(&accounts,
+               args.iter().any(|arg| arg == "--csv"),
+               args.iter().any(|arg| arg == "--monthly"));
+        return;
+    }
+
+    if subcommand(&args) == Some("traffic") {
+        exit(traffic(&accounts, &args, &*format));
+    }
+
+    // accounts are queried one by one, the worst state wins the exit code
+    let mut code = EXIT_ENABLED;
+    let mut accts = Vec::new();
+    for creds in accounts {
+        match fetch_acct(&creds) {
+            Ok(acct) => accts.push((creds, acct)),
+            Err(err) => {
+                log(&format!("{}{}", creds.prefix(), err));
+                code = EXIT_ERROR;
+            }
+        }
+    }
+
+    let named: Vec<(Option<String>, AcctInfo)> = accts.iter()
+                                                      .map(|&(ref creds, ref acct)| (creds.name.clone(), acct.clone()))
+                                                      .collect();
+    print_accts(&named, &*format);
+
+    let force_credit = subcommand(&args) == Some("credit");
+    for &(ref creds, ref acct) in &accts {
+        code = cmp::max(code, check_account(creds, acct, force_credit));
+    }
+
+    exit(code);
 }
 
 #[bench]
-#[ignore]
-fn bench_main(b: &mut Bencher) {
-    b.iter(|| main());
+fn bench_parse_acct(b: &mut Bencher) {
+    let page = fixture("enabled.htm");
+    b.iter(|| parse_acct(&*page));
+}
+
+/// Synthetic test page, see `tests/fixtures/adslby/README.md`.
+#[cfg(test)]
+fn fixture(name: &str) -> String {
+    use std::fs::File;
+
+    let mut buf = Vec::new();
+    File::open(format!("{}/tests/fixtures/adslby/{}", env!("CARGO_MANIFEST_DIR"), name))
+        .and_then(|mut f| f.read_to_end(&mut buf))
+        .unwrap();
+    WINDOWS_1251.decode(&*buf, DecoderTrap::Strict).unwrap()
+}
+
+#[test]
+fn test_parse_acct() {
+    let acct = parse_acct(&*fixture("enabled.htm")).unwrap();
+    assert!(acct.enabled);
+    assert_eq!(acct.account, 12345.67);
+    assert_eq!(acct.days, 25);
+    assert_eq!(acct.price, 2);
+    assert_eq!(acct.credit, None);
+
+    let acct = parse_acct(&*fixture("negative.htm")).unwrap();
+    assert!(!acct.enabled);
+    assert_eq!(acct.account, -15.3);
+    assert_eq!(acct.days, -3);
+    assert_eq!(acct.credit, Some(20));
+
+    assert_eq!(parse_acct(&*fixture("broken.htm")).unwrap_err(),
+               ParseError {
+                   page: "account",
+                   missing: vec!["account", "days", "price"],
+               });
+}
+
+#[test]
+fn test_parse_traffic() {
+    let records = parse_traffic(&*fixture("traffic.htm")).unwrap();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0],
+               TrafficRecord {
+                   date: "2016-05-24".to_owned(),
+                   time: Some("08:12".to_owned()),
+                   mb_in: 1234.5,
+                   mb_out: 56.25,
+                   cost: 2.5,
+               });
+    assert_eq!(records[2].date, "2016-05-25");
+
+    let days = traffic_by_day(records);
+    assert_eq!(days.len(), 2);
+    assert_eq!(days[0].cost, 3.0);
+    assert_eq!(days[0].time, None);
+
+    assert_eq!(parse_traffic(&*fixture("enabled.htm")).unwrap_err(),
+               ParseError {
+                   page: "traffic",
+                   missing: vec!["date", "in", "out", "cost"],
+               });
+}
+
+#[test]
+fn test_parse_date() {
+    assert_eq!(parse_date("25.05.2016 13:45"),
+               Some(("2016-05-25".to_owned(), Some("13:45".to_owned()))));
+    assert_eq!(parse_date("2016-05-25"), Some(("2016-05-25".to_owned(), None)));
+    assert_eq!(parse_date("Итого"), None);
+    assert_eq!(parse_date("5.5.16"), None);
+}
+
+#[test]
+fn test_table_rows() {
+    assert_eq!(table_rows("<table><tr><td class='left'>Label</td>\n<td><b>1&nbsp;234</b> руб.<br>x</td></tr>\
+                           <tr><th>Only</th></tr></table>"),
+               vec![vec!["Label".to_owned(), "1 234 руб. x".to_owned()], vec!["Only".to_owned()]]);
+}
+
+#[test]
+fn test_parse_credit_response() {
+    assert_eq!(parse_credit_response("var info = { stat: 'Включен', credit: 50 };"),
+               CreditResult {
+                   enabled: true,
+                   status: Some("Включен".to_owned()),
+               });
+    assert_eq!(parse_credit_response("{ stat: 'Выключен' }").enabled, false);
+    assert_eq!(parse_credit_response("error"),
+               CreditResult {
+                   enabled: false,
+                   status: None,
+               });
+}
+
+#[test]
+fn test_credit_policy() {
+    let acct = AcctInfo {
+        enabled: true,
+        account: 3.0,
+        days: 1,
+        price: 2,
+        credit: Some(50),
+    };
+    let policy = CreditPolicy {
+        auto: Some(true),
+        min_days: Some(2),
+        max_percent: None,
+        interval: None,
+    };
+    assert_eq!(credit_reason(&acct, &policy), Some("1 days left".to_owned()));
+    assert_eq!(credit_reason(&AcctInfo { days: 5, ..acct }, &policy), None);
+    assert!(credit_reason(&AcctInfo { days: 5, account: -1.0, ..acct }, &policy).is_some());
+
+    assert_eq!(check_credit_limits(&acct, &policy, None, 100000), Ok(()));
+    assert_eq!(check_credit_limits(&acct, &policy, Some(100000 - 3600), 100000),
+               Err("credit was already enabled 1 hours ago".to_owned()));
+    assert!(check_credit_limits(&acct, &CreditPolicy { max_percent: Some(20), ..policy.clone() }, None, 0).is_err());
+    assert!(check_credit_limits(&AcctInfo { credit: None, ..acct }, &policy, None, 0).is_err());
+}
+
+#[cfg(test)]
+fn reading(time: i64, account: f32) -> Reading {
+    Reading {
+        time: time,
+        acct: AcctInfo {
+            enabled: true,
+            account: account,
+            days: 10,
+            price: 2,
+            credit: None,
+        },
+    }
+}
+
+#[test]
+fn test_summarize() {
+    let readings = vec![reading(0, 100.0),
+                        reading(3600, 90.0),
+                        reading(86400, 80.0),
+                        reading(86400 + 3600, 130.0),
+                        reading(2 * 86400, 120.0)];
+    let daily = summarize(&readings, |t| (t / 86400).to_string());
+    assert_eq!(daily,
+               vec![Summary { period: "0".to_owned(), spent: 10.0, topped_up: 0.0, balance: 90.0 },
+                    Summary { period: "1".to_owned(), spent: 10.0, topped_up: 50.0, balance: 130.0 },
+                    Summary { period: "2".to_owned(), spent: 10.0, topped_up: 0.0, balance: 120.0 }]);
+
+    assert_eq!(burn_rate(&readings), Some(15.0));
+    assert_eq!(burn_rate(&readings[..1]), None);
+
+    assert_eq!(days_mismatch(10.0, 12), None);
+    assert_eq!(days_mismatch(20.0, 12), Some(8.0));
+    assert_eq!(days_mismatch(2.0, 12), Some(-10.0));
+}
+
+#[test]
+fn test_subcommand() {
+    let args = |line: &str| -> Vec<String> { line.split_whitespace().map(|arg| arg.to_owned()).collect() };
+    assert_eq!(subcommand(&args("adslbystat")), None);
+    assert_eq!(subcommand(&args("adslbystat --csv report --monthly")), Some("report"));
+    assert_eq!(subcommand(&args("adslbystat --format json traffic")), Some("traffic"));
+    assert_eq!(subcommand(&args("adslbystat --account credit")), None);
+    assert_eq!(subcommand(&args("adslbystat --account=home credit")), Some("credit"));
+}
+
+#[test]
+fn test_parse_reading() {
+    let reading = parse_reading("1464000000\t-15.3\t-3\t2\t20\t0").unwrap();
+    assert_eq!(reading.time, 1464000000);
+    assert_eq!(reading.acct.account, -15.3);
+    assert_eq!(reading.acct.credit, Some(20));
+    assert!(!reading.acct.enabled);
+    assert!(parse_reading("garbage").is_none());
+}
+
+#[test]
+fn test_active_alerts() {
+    let acct = AcctInfo {
+        enabled: true,
+        account: 1.5,
+        days: 3,
+        price: 2,
+        credit: None,
+    };
+    let alerts = AlertsConfig {
+        min_account: Some(5.0),
+        min_days: None,
+    };
+    assert_eq!(active_alerts(&acct, &alerts).iter().map(|&(name, _)| name).collect::<Vec<_>>(),
+               vec!["account"]);
+
+    let alerts = AlertsConfig {
+        min_account: Some(1.0),
+        min_days: Some(5),
+    };
+    assert_eq!(active_alerts(&acct, &alerts).iter().map(|&(name, _)| name).collect::<Vec<_>>(),
+               vec!["days"]);
+}
+
+#[test]
+fn test_prometheus() {
+    let acct = AcctInfo {
+        enabled: true,
+        account: 12.5,
+        days: 3,
+        price: 2,
+        credit: None,
+    };
+    let output = prometheus(&[(None, acct.clone())]);
+    assert!(output.starts_with("# HELP adsl_account_balance_rub "));
+    assert!(output.contains("\n# TYPE adsl_days_left gauge\nadsl_days_left 3\n"));
+    assert!(output.ends_with("adsl_enabled 1\n"));
+    assert!(!output.contains("adsl_credit_percent"));
+
+    let output = prometheus(&[(Some("home".to_owned()), acct.clone()),
+                              (Some("office".to_owned()), AcctInfo { credit: Some(20), ..acct })]);
+    assert_eq!(output.matches("# TYPE adsl_days_left gauge").count(), 1);
+    assert!(output.contains("adsl_days_left{account=\"home\"} 3\nadsl_days_left{account=\"office\"} 3\n"));
+    assert!(output.contains("\n# TYPE adsl_credit_percent gauge\nadsl_credit_percent{account=\"office\"} 20\n"));
+}
+
+#[cfg(test)]
+fn creds(name: Option<&str>) -> Creds {
+    Creds {
+        name: name.map(|name| name.to_owned()),
+        username: "user".to_owned(),
+        password: "secret".to_owned(),
+        base_url: None,
+        traffic_url: None,
+        alerts: None,
+        credit: None,
+    }
+}
+
+#[test]
+fn test_accounts() {
+    let config = Config {
+        username: Some("user".to_owned()),
+        password: Some("secret".to_owned()),
+        base_url: Some("http://localhost:8080/".to_owned()),
+        traffic_url: None,
+        alerts: Some(AlertsConfig {
+            min_account: Some(5.0),
+            min_days: None,
+        }),
+        credit: None,
+        account: None,
+    };
+    let single = accounts(config.clone()).unwrap();
+    assert_eq!(single.len(), 1);
+    assert_eq!(single[0].name, None);
+    assert_eq!(single[0].state(HISTORY_STATE), "adslby/history");
+    assert_eq!(single[0].url("/001.htm"), "http://localhost:8080/001.htm");
+    assert_eq!(creds(None).url("/001.htm"), "https://www.adsl.by/001.htm");
+
+    let named = accounts(Config { account: Some(vec![creds(Some("home")), creds(Some("office"))]), ..config.clone() })
+                    .unwrap();
+    assert_eq!(named.len(), 2);
+    assert_eq!(named[1].state(HISTORY_STATE), "adslby/history-office");
+    assert_eq!(named[1].prefix(), "office: ");
+    assert_eq!(named[1].alerts.as_ref().and_then(|alerts| alerts.min_account), Some(5.0));
+
+    assert!(accounts(Config { account: Some(vec![creds(None)]), ..config.clone() }).is_err());
+    assert!(accounts(Config { account: Some(vec![creds(Some("../home"))]), ..config.clone() }).is_err());
+    assert!(accounts(Config { account: Some(vec![creds(Some("home/x"))]), ..config.clone() }).is_err());
+    assert!(accounts(Config { account: Some(vec![creds(Some(""))]), ..config.clone() }).is_err());
+    assert!(accounts(Config { account: Some(vec![creds(Some("home_2-a"))]), ..config.clone() }).is_ok());
+    assert!(accounts(Config { account: Some(vec![creds(Some("home")), creds(Some("home"))]), ..config.clone() })
+                .is_err());
+    assert!(accounts(Config { username: None, ..config }).is_err());
 }
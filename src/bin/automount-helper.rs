@@ -4,8 +4,10 @@
 extern crate test;
 
 use std::env;
+use std::fmt;
 use std::io::{self, Write};
 use std::path::Path;
+use std::process::exit;
 use std::fs::metadata;
 use std::os::unix::fs::MetadataExt;
 
@@ -43,10 +45,14 @@ fn ismount(dir: &str) -> bool {
 
 }
 
+/// Escapes bytes into a unit name part the way `systemd-escape` does:
+/// `/` becomes `-`, ASCII alphanumerics, `_`, `:` and `.` are kept
+/// (except a leading `.`), any other byte is written as `\xNN`.
 struct SystemdEscape<I: Iterator<Item=u8>> {
     iter: I,
     buf: Option<[u8; 3]>,
     idx: i8,
+    first: bool,
 }
 
 impl<I: Iterator<Item=u8>> SystemdEscape<I> {
@@ -55,6 +61,7 @@ impl<I: Iterator<Item=u8>> SystemdEscape<I> {
             iter: iter.into_iter(),
             buf: None,
             idx: 0,
+            first: true,
         }
     }
 
@@ -81,10 +88,15 @@ impl<I: Iterator<Item=u8>> Iterator for SystemdEscape<I> {
             None => ()
         }
 
+        let first = self.first;
+        self.first = false;
+
         match self.iter.next() {
             None => None,
             Some(c) => match c {
-                b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'_' => Some(c as char),
+                b'/' => Some('-'),
+                b'.' if !first => Some('.'),
+                b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'_' | b':' => Some(c as char),
                 _ => {
                     self.buf = Some([b'x', Self::hex((c & 0xf0) >> 4), Self::hex(c & 0x0f)]);
                     self.idx = -1;
@@ -95,11 +107,153 @@ impl<I: Iterator<Item=u8>> Iterator for SystemdEscape<I> {
     }
 }
 
+#[derive(Debug, PartialEq)]
+enum EscapeError {
+    InvalidEscape,
+    NotNormalized,
+    EmptyPath,
+}
+
+impl fmt::Display for EscapeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            EscapeError::InvalidEscape => "invalid escape sequence",
+            EscapeError::NotNormalized => "path is not normalized",
+            EscapeError::EmptyPath => "path is empty",
+        })
+    }
+}
+
+/// Reverses `SystemdEscape`: `-` becomes `/` and `\xNN` becomes the byte,
+/// other bytes are passed as is, like `systemd-escape --unescape`.
+struct SystemdUnescape<I: Iterator<Item=u8>> {
+    iter: I,
+}
+
+impl<I: Iterator<Item=u8>> SystemdUnescape<I> {
+    fn new<II: IntoIterator<Item=u8, IntoIter=I>>(iter: II) -> SystemdUnescape<I> {
+        SystemdUnescape { iter: iter.into_iter() }
+    }
+
+    fn unhex(x: u8) -> Option<u8> {
+        match x {
+            b'0'...b'9' => Some(x - b'0'),
+            b'a'...b'f' => Some(x - b'a' + 0xa),
+            b'A'...b'F' => Some(x - b'A' + 0xa),
+            _ => None
+        }
+    }
+}
+
+impl<I: Iterator<Item=u8>> Iterator for SystemdUnescape<I> {
+    type Item = Result<u8, EscapeError>;
+    fn next(&mut self) -> Option<Result<u8, EscapeError>> {
+        let c = match self.iter.next() {
+            Some(c) => c,
+            None => return None,
+        };
+
+        Some(match c {
+            b'-' => Ok(b'/'),
+            b'\\' => {
+                let x = self.iter.next();
+                let hi = self.iter.next().and_then(Self::unhex);
+                let lo = self.iter.next().and_then(Self::unhex);
+                match (x, hi, lo) {
+                    (Some(b'x'), Some(hi), Some(lo)) => Ok(hi << 4 | lo),
+                    _ => Err(EscapeError::InvalidEscape),
+                }
+            }
+            _ => Ok(c),
+        })
+    }
+}
+
 fn systemd_encode(inp: &str) -> String {
     SystemdEscape::new(inp.as_bytes().into_iter().cloned()).collect()
 }
 
+/// Path mode of `systemd-escape`: duplicate, leading and trailing slashes
+/// are dropped, root becomes `-`. Empty paths and paths with `.` or `..`
+/// components are refused like systemd does.
+fn systemd_encode_path(path: &str) -> Result<String, EscapeError> {
+    if path.is_empty() {
+        return Err(EscapeError::EmptyPath);
+    }
+
+    let parts: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+    if parts.iter().any(|&part| part == "." || part == "..") {
+        return Err(EscapeError::NotNormalized);
+    }
+
+    if parts.is_empty() {
+        Ok("-".to_owned())
+    } else {
+        Ok(systemd_encode(&*parts.join("/")))
+    }
+}
+
+fn systemd_decode(inp: &str) -> Result<Vec<u8>, EscapeError> {
+    SystemdUnescape::new(inp.bytes()).collect()
+}
+
+/// Reverses `systemd_encode_path`, the result is an absolute normalized path.
+fn systemd_decode_path(inp: &str) -> Result<Vec<u8>, EscapeError> {
+    if inp == "-" {
+        return Ok(vec![b'/']);
+    }
+
+    let mut path = vec![b'/'];
+    path.extend(try!(systemd_decode(inp)));
+    if path.split(|&c| c == b'/').skip(1).all(|part| !part.is_empty() && part != &b"."[..] && part != &b".."[..]) {
+        Ok(path)
+    } else {
+        Err(EscapeError::NotNormalized)
+    }
+}
+
+/// `--escape` and `--unescape` modes with optional `--path`, like
+/// `systemd-escape`, for udev rules and scripts. `--path` alone
+/// means escaping. Returns exit code.
+fn escape_args(args: &[String]) -> i32 {
+    let unescape = args.iter().any(|arg| arg == "--unescape");
+    let path = args.iter().any(|arg| arg == "--path");
+
+    let mut out = io::stdout();
+    let mut code = 0;
+    for arg in args.iter().filter(|arg| !arg.starts_with("--")) {
+        let result = match (unescape, path) {
+            (false, false) => Ok(systemd_encode(arg).into_bytes()),
+            (false, true) => systemd_encode_path(arg).map(String::into_bytes),
+            (true, false) => systemd_decode(arg),
+            (true, true) => systemd_decode_path(arg),
+        };
+        match result {
+            Ok(bytes) => {
+                out.write_all(&*bytes).unwrap();
+                out.write_all(b"\n").unwrap();
+            }
+            Err(err) => {
+                writeln!(io::stderr(), "Failed to process {}: {}", arg, err).unwrap();
+                code = 1;
+            }
+        }
+    }
+    out.flush().unwrap();
+    code
+}
+
+/// Whether the helper is called as `systemd-escape` rather than by udev.
+fn is_escape_call(args: &[String]) -> bool {
+    args.iter().any(|arg| arg == "--escape" || arg == "--unescape" || arg == "--path")
+}
+
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if is_escape_call(&args) {
+        exit(escape_args(&args));
+    }
+
     let mut name = automount_name();
 
     while ismount(&*format!("/media/{}", name)) {
@@ -110,9 +264,9 @@ fn main() {
 
     let mut out = io::stdout();
     out.write_all(name.as_bytes()).unwrap();
-    out.write(&[0x0a]).unwrap();
+    out.write_all(b"\n").unwrap();
     out.write_all(systemd_encode(&*service_name).as_bytes()).unwrap();
-    out.write(&[0x0a]).unwrap();
+    out.write_all(b"\n").unwrap();
     out.flush().unwrap();
 }
 
@@ -128,7 +282,121 @@ fn test_ismount() {
 fn test_systemd_encode() {
     assert_eq!(&*systemd_encode("hello_W0rld"), "hello_W0rld");
     assert_eq!(&*systemd_encode(r"/dev/sda1 /media/path"),
-               r"\x2fdev\x2fsda1\x20\x2fmedia\x2fpath");
+               r"-dev-sda1\x20-media-path");
+    assert_eq!(&*systemd_encode("Hallöchen, Meister"), r"Hall\xc3\xb6chen\x2c\x20Meister");
+    assert_eq!(&*systemd_encode(".config/x-y:1.2"), r"\x2econfig-x\x2dy:1.2");
+    assert_eq!(&*systemd_encode(""), "");
+}
+
+#[test]
+fn test_systemd_encode_path() {
+    assert_eq!(systemd_encode_path("/tmp//waldi/foobar/"), Ok("tmp-waldi-foobar".to_owned()));
+    assert_eq!(systemd_encode_path("/"), Ok("-".to_owned()));
+    assert_eq!(systemd_encode_path(""), Err(EscapeError::EmptyPath));
+    assert_eq!(systemd_encode_path("/.hidden/dir.d"), Ok(r"\x2ehidden-dir.d".to_owned()));
+    assert_eq!(systemd_encode_path("/media/my disk"), Ok(r"media-my\x20disk".to_owned()));
+    assert_eq!(systemd_encode_path("/tmp/../etc"), Err(EscapeError::NotNormalized));
+    assert_eq!(systemd_encode_path("./tmp"), Err(EscapeError::NotNormalized));
+}
+
+#[test]
+fn test_systemd_decode() {
+    assert_eq!(systemd_decode(r"Hall\xc3\xb6chen\x2c\x20Meister"),
+               Ok("Hallöchen, Meister".as_bytes().to_vec()));
+    assert_eq!(systemd_decode(r"-dev-sda1\x2A"), Ok(b"/dev/sda1*".to_vec()));
+    assert_eq!(systemd_decode(r"a\x2"), Err(EscapeError::InvalidEscape));
+    assert_eq!(systemd_decode(r"a\y20"), Err(EscapeError::InvalidEscape));
+    assert_eq!(systemd_decode(r"a\xzz"), Err(EscapeError::InvalidEscape));
+
+    assert_eq!(systemd_decode_path("tmp-waldi-foobar"), Ok(b"/tmp/waldi/foobar".to_vec()));
+    assert_eq!(systemd_decode_path("-"), Ok(b"/".to_vec()));
+    assert_eq!(systemd_decode_path("-tmp"), Err(EscapeError::NotNormalized));
+    assert_eq!(systemd_decode_path("tmp-"), Err(EscapeError::NotNormalized));
+    assert_eq!(systemd_decode_path(r"tmp-\x2e\x2e"), Err(EscapeError::NotNormalized));
+}
+
+/// Byte strings of up to 3 bytes over an alphabet with a byte of every
+/// class the escaping rules tell apart.
+#[cfg(test)]
+fn samples() -> Vec<Vec<u8>> {
+    let alphabet = b"aZ9_:.-/\\x \xc3\xff";
+    let mut samples = vec![vec![]];
+    let mut last = vec![vec![]];
+    for _ in 0..3 {
+        last = last.iter()
+                   .flat_map(|prefix: &Vec<u8>| {
+                       alphabet.iter().map(move |&c| {
+                           let mut sample = prefix.clone();
+                           sample.push(c);
+                           sample
+                       })
+                   })
+                   .collect();
+        samples.extend(last.iter().cloned());
+    }
+    samples
+}
+
+/// Expectations from `systemd-escape` and systemd's test-unit-name.
+#[test]
+fn test_systemd_escape_bytes() {
+    let escape = |inp: &[u8]| -> String { SystemdEscape::new(inp.to_vec()).collect() };
+    assert_eq!(escape(b"ab+-c.a/bc@foo.service"), r"ab\x2b\x2dc.a-bc\x40foo.service");
+    assert_eq!(escape(b"-"), r"\x2d");
+    assert_eq!(escape(b"\\"), r"\x5c");
+    assert_eq!(escape(b"/"), "-");
+    assert_eq!(escape(b".foo"), r"\x2efoo");
+    assert_eq!(escape(b"a.b:c"), "a.b:c");
+    assert_eq!(escape(b"_x9"), "_x9");
+    assert_eq!(escape(b"a\xffb"), r"a\xffb");
+
+    assert_eq!(systemd_encode_path("/.foo"), Ok(r"\x2efoo".to_owned()));
+    assert_eq!(systemd_encode_path("/foo/.bar"), Ok("foo-.bar".to_owned()));
+    assert_eq!(systemd_encode_path(""), Err(EscapeError::EmptyPath));
+
+    // `--path` alone escapes like `--escape --path`
+    assert!(is_escape_call(&["--path".to_owned(), "/media".to_owned()]));
+    assert!(!is_escape_call(&[]));
+}
+
+#[test]
+fn test_systemd_escape_properties() {
+    for sample in samples() {
+        let escaped: String = SystemdEscape::new(sample.clone()).collect();
+
+        // only unit name characters, never a leading dot
+        assert!(escaped.chars().all(|c| {
+                    match c {
+                        'a'...'z' | 'A'...'Z' | '0'...'9' | '_' | ':' | '.' | '-' | '\\' => true,
+                        _ => false,
+                    }
+                }),
+                "{:?} escaped to {}",
+                sample,
+                escaped);
+        assert!(!escaped.starts_with('.'));
+
+        // escaping is reversible
+        let unescaped: Result<Vec<u8>, EscapeError> = SystemdUnescape::new(escaped.bytes()).collect();
+        assert_eq!(unescaped, Ok(sample.clone()));
+
+        // path mode: normalized paths round trip, others are refused
+        if let Ok(path) = String::from_utf8(sample.clone()) {
+            let parts: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+            let normalized = format!("/{}", parts.join("/"));
+            match systemd_encode_path(&*path) {
+                Ok(escaped) => {
+                    assert!(!parts.contains(&".") && !parts.contains(&".."));
+                    assert_eq!(systemd_decode_path(&*escaped), Ok(normalized.into_bytes()));
+                }
+                Err(EscapeError::EmptyPath) => assert!(path.is_empty()),
+                Err(err) => {
+                    assert_eq!(err, EscapeError::NotNormalized);
+                    assert!(parts.contains(&".") || parts.contains(&".."));
+                }
+            }
+        }
+    }
 }
 
 #[test]
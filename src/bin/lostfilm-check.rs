@@ -6,11 +6,12 @@ extern crate regex;
 extern crate serde;
 extern crate script_utils as utils;
 extern crate xml;
-extern crate pb;
 #[macro_use]
 extern crate log;
 
-use std::io::Read;
+use std::env;
+use std::io::{self, Read, Write};
+use std::process::exit;
 
 use encoding::{Encoding, DecoderTrap};
 use encoding::all::WINDOWS_1251;
@@ -28,7 +29,7 @@ use url::{Url, UrlParser, form_urlencoded};
 use xml::reader::{EventReader, XmlEvent};
 use xml::name::OwnedName;
 use regex::Regex;
-use pb::{PbAPI, PushMsg, TargetIden, Push, PushData};
+use utils::notify::{Notifier, Chain, Template};
 
 static USER_AGENT: &'static str = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, \
                                    like Gecko) Chrome/33.0.1750.152 Safari/537.36";
@@ -38,19 +39,14 @@ static LOGIN_URL: &'static str = "http://login1.bogi.ru/login.php";
 
 include!(concat!(env!("OUT_DIR"), "/lostfilm-check.rs"));
 
-fn notify(api: &mut PbAPI, device_iden: Option<String>, title: &str, url: &str) {
+fn notify<N: Notifier>(notifier: &mut N, template: &Template, title: &str, url: &str) {
     println!("added torrent {}: {}", title, url);
 
-    let push = PushMsg {
-        title: Some("New LostFilm release".into()),
-        body: Some(title.into()),
-        target: TargetIden::CurrentUser,
-        data: PushData::Link(Url::parse(url).ok()),
-        source_device_iden: device_iden,
-    };
-
-    if let Ok(result @ Push {..}) = api.send(&push) {
-        println!("notified with push {}", result.iden);
+    let result = template.render(&[("title", title), ("url", url)])
+                         .and_then(|msg| notifier.notify(&msg));
+    match result {
+        Ok(receipt) => println!("notified with {}", receipt),
+        Err(err) => println!("{}", err),
     }
 }
 
@@ -335,14 +331,32 @@ impl TransmissionAPI {
 }
 
 fn main() {
+    if utils::config::strip_overrides(env::args()).len() > 1 {
+        println!("usage: lostfilm-check [--set lostfilm.<key>=<value>]...");
+        exit(utils::config::EXIT_USAGE);
+    }
+
     debug!("1. loading configs...");
-    let config: Config = utils::load_config("lostfilm/config.toml").expect("config file missing");
+    let config: Config = utils::load_config_verbose("lostfilm/config.toml");
     let download_dir = config.download_dir.as_ref().map(|v| &**v);
 
     debug!("2. initializing api objects...");
-    let pbcfg = utils::load_config::<PbConfig>("pushbullet/config.toml")
-                    .expect("pushbullet config missing");
-    let mut pbapi = PbAPI::new(&*pbcfg.access_token);
+    let mut notifier = match Chain::from_config() {
+        Ok(notifier) => notifier,
+        Err(err) => {
+            let _ = writeln!(io::stderr(), "{}", err);
+            exit(err.exit_code());
+        }
+    };
+    let template = match Template::new("New LostFilm release", "{title}")
+                             .url("{url}")
+                             .load("new_release") {
+        Ok(template) => template,
+        Err(err) => {
+            let _ = writeln!(io::stderr(), "{}", err);
+            exit(err.exit_code());
+        }
+    };
     let mut trans = TransmissionAPI::new();
 
     debug!("3. logging in to lostfilm.tv...");
@@ -354,7 +368,7 @@ fn main() {
     debug!("5. adding found torrents to transmission...");
     for (title, url) in urls.into_iter() {
         if trans.add_torrent(&*url, download_dir) {
-            notify(&mut pbapi, pbcfg.device_iden.clone(), &*title, &*url);
+            notify(&mut notifier, &template, &*title, &*url);
         }
     }
 
@@ -1,25 +1,27 @@
-extern crate pb;
-extern crate serde;
 extern crate script_utils as utils;
 
-use pb::{PbAPI, PushMsg, TargetIden, Push, PushData};
+use utils::notify::{self, Template};
 use std::env;
-
-include!(concat!(env!("OUT_DIR"), "/trans-done-pb.rs"));
+use std::process::exit;
 
 fn main() {
-    let pbcfg = utils::load_config::<Config>("pushbullet/config.toml").unwrap();
-    let mut api = PbAPI::new(&*pbcfg.access_token);
+    if utils::config::strip_overrides(env::args()).len() > 1 {
+        println!("usage: trans-done-pb [--set notify.<key>=<value>]...");
+        exit(utils::config::EXIT_USAGE);
+    }
+
     let torrent_name = env::var("TR_TORRENT_NAME").unwrap();
     let torrent_dir = env::var("TR_TORRENT_DIR").unwrap();
-    let push = PushMsg {
-        title: Some("Torrent download complete".into()),
-        body: Some(format!("{} downloaded to {}", torrent_name, torrent_dir).into()),
-        target: TargetIden::CurrentUser,
-        data: PushData::Note,
-        source_device_iden: pbcfg.device_iden,
-    };
+    let template = Template::new("Torrent download complete",
+                                 "{torrent_name} downloaded to {torrent_dir}");
 
-    let result: Push = api.send(&push).unwrap();
-    println!("notified with push {}", result.iden);
+    match notify::send_event("torrent_done",
+                             template,
+                             &[("torrent_name", &*torrent_name), ("torrent_dir", &*torrent_dir)]) {
+        Ok(receipt) => println!("notified with {}", receipt),
+        Err(err) => {
+            println!("{}", err);
+            exit(1);
+        }
+    }
 }
@@ -6,13 +6,20 @@ extern crate serde;
 
 use pocket::Pocket;
 use inotify::{INotify, ffi};
+use std::env;
 use std::fs::File;
 use std::io::{BufReader, BufRead};
+use std::process::exit;
 
 include!(concat!(env!("OUT_DIR"), "/vimb-queue-pocket.rs"));
 
 fn main() {
-    let config: Creds = utils::load_config("pocket/creds.toml").expect("config file load error");
+    if utils::config::strip_overrides(env::args()).len() > 1 {
+        println!("usage: vimb-queue-pocket [--set pocket.<key>=<value>]...");
+        exit(utils::config::EXIT_USAGE);
+    }
+
+    let config: Creds = utils::load_config_verbose("pocket/creds.toml");
     let mut pocket = Pocket::new(&*config.consumer_key, Some(&*config.access_token));
 
     let queue = xdg::get_config_dirs()
@@ -1,96 +1,1131 @@
-extern crate pb;
 extern crate script_utils as utils;
-extern crate yadns;
 extern crate serde;
-extern crate lettre;
+extern crate serde_json;
+extern crate libc;
+extern crate time;
 
+use std::cmp;
+use std::collections::BTreeMap;
 use std::env;
-use std::net::Ipv4Addr;
-use yadns::{YandexDNS, ListRequest, AddRequest, DnsType};
-use pb::{PbAPI, PushMsg, TargetIden, Push, PushData};
-use lettre::transport::smtp::SmtpTransportBuilder;
-use lettre::transport::EmailTransport;
-use lettre::email::EmailBuilder;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{exit, Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use std::sync::mpsc::{channel, Sender, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use utils::dns::{self, DnsProvider, DnsRecord, ProviderConfig};
+use utils::ipsource::{self, Family, IpSource, IpSourceConfig};
+use utils::notify::{self, Chain, NotifyConfig, SmtpConfig, Template};
 
 include!(concat!(env!("OUT_DIR"), "/yaddns.rs"));
 
-fn get_my_ip_address() -> Option<Ipv4Addr> {
-    use std::net::{TcpStream, SocketAddr};
-    let addr = TcpStream::connect(("8.8.8.8", 53)).and_then(|s| s.local_addr());
-    match addr {
-        Ok(SocketAddr::V4(addr)) => Some(*addr.ip()),
-        _ => None,
+trait RecordFamily {
+    fn dns_type_name(&self) -> &'static str;
+    fn last_ip_state(&self) -> &'static str;
+}
+
+impl RecordFamily for Family {
+    fn dns_type_name(&self) -> &'static str {
+        match *self {
+            Family::V4 => "A",
+            Family::V6 => "AAAA",
+        }
+    }
+
+    fn last_ip_state(&self) -> &'static str {
+        match *self {
+            Family::V4 => "yadns/last_ip",
+            Family::V6 => "yadns/last_ipv6",
+        }
     }
 }
 
-fn main() {
-    let pbcfg = utils::load_config::<PbConfig>("pushbullet/config.toml").unwrap();
-    let mut pbapi = PbAPI::new(&*pbcfg.access_token);
+/// A single DNS record to keep pointed to our IP address.
+#[derive(Debug, Clone)]
+struct Record {
+    domain: String,
+    subdomain: String,
+    family: Family,
+    ttl: Option<u32>,
+    provider: ProviderConfig,
+}
+
+impl Record {
+    fn name(&self) -> String {
+        if self.subdomain == "@" {
+            self.domain.clone()
+        } else {
+            format!("{}.{}", self.subdomain, self.domain)
+        }
+    }
+}
+
+/// Finds provider for a domain: one of `[provider.<name>]` sections if named,
+/// Yandex with the given or the default token otherwise.
+fn provider_config(config: &Config,
+                   name: Option<&String>,
+                   token: Option<&String>,
+                   domain: &str)
+                   -> Result<ProviderConfig, String> {
+    match name {
+        Some(name) => {
+            config.provider
+                  .as_ref()
+                  .and_then(|providers| providers.get(name))
+                  .cloned()
+                  .ok_or_else(|| format!("unknown provider {} for {}", name, domain))
+        }
+        None => {
+            token.or(config.token.as_ref())
+                 .map(|token| dns::yandex_config(token.clone()))
+                 .ok_or_else(|| format!("no token for {}", domain))
+        }
+    }
+}
+
+/// Records listed in `[[record]]` sections, or a single record
+/// from top-level `domain` and `subdomain` (A and optionally AAAA).
+fn config_records(config: &Config) -> Result<Vec<Record>, String> {
+
+    match config.record {
+        Some(ref records) => {
+            records.iter()
+                   .map(|rec| {
+                       let family = match rec.kind.as_ref().map(|k| &**k).unwrap_or("A") {
+                           "A" => Family::V4,
+                           "AAAA" => Family::V6,
+                           other => {
+                               return Err(format!("unsupported record type {} for {}.{}",
+                                                  other,
+                                                  rec.subdomain,
+                                                  rec.domain))
+                           }
+                       };
+                       Ok(Record {
+                           domain: rec.domain.clone(),
+                           subdomain: rec.subdomain.clone(),
+                           family: family,
+                           ttl: rec.ttl.or(config.ttl),
+                           provider: try!(provider_config(config,
+                                                          rec.provider.as_ref(),
+                                                          rec.token.as_ref(),
+                                                          &*rec.domain)),
+                       })
+                   })
+                   .collect()
+        }
+        None => {
+            let (domain, subdomain) = match (&config.domain, &config.subdomain) {
+                (&Some(ref domain), &Some(ref subdomain)) => (domain.clone(), subdomain.clone()),
+                _ => return Err("either [[record]] sections or domain and subdomain must be set".to_owned()),
+            };
+
+            let mut families = Vec::new();
+            if config.ipv4.unwrap_or(true) {
+                families.push(Family::V4);
+            }
+            if config.ipv6.unwrap_or(false) {
+                families.push(Family::V6);
+            }
+
+            let provider = try!(provider_config(config, None, None, &*domain));
+            Ok(families.into_iter()
+                       .map(|family| {
+                           Record {
+                               domain: domain.clone(),
+                               subdomain: subdomain.clone(),
+                               family: family,
+                               ttl: config.ttl,
+                               provider: provider.clone(),
+                           }
+                       })
+                       .collect())
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Change {
+    domain: String,
+    name: String,
+    family: Family,
+    ip: String,
+    old_ip: String,
+    source: String,
+    verified: Option<String>,
+}
+
+fn save_last_ip(family: Family, ip: &str) {
+    if let Err(err) = utils::state::write_state(family.last_ip_state(), ip) {
+        log(&format!("can't save last IP address: {}", err));
+    }
+}
 
-    let config = utils::load_config::<Config>("yadns/config.toml").unwrap();
+enum Action {
+    Add,
+    Edit(DnsRecord),
+    Delete(DnsRecord),
+}
+
+/// A planned change of a single DNS record.
+struct Op {
+    record: Record,
+    action: Action,
+    old: Option<String>,
+    new: Option<String>,
+}
+
+impl Action {
+    fn name(&self) -> &'static str {
+        match *self {
+            Action::Add => "add",
+            Action::Edit(_) => "edit",
+            Action::Delete(_) => "delete",
+        }
+    }
+
+    /// Writes a diff-like line describing the action.
+    fn describe(&self,
+                f: &mut fmt::Formatter,
+                name: &str,
+                kind: &str,
+                old: Option<&String>,
+                new: Option<&String>)
+                -> fmt::Result {
+        let none = String::new();
+        let (old, new) = (old.unwrap_or(&none), new.unwrap_or(&none));
+        match *self {
+            Action::Add => write!(f, "+ {} {} {}", name, kind, new),
+            Action::Edit(_) => write!(f, "~ {} {} {} -> {}", name, kind, old, new),
+            Action::Delete(_) => write!(f, "- {} {} {}", name, kind, old),
+        }
+    }
+}
+
+/// Planned change which can be shown to the user.
+trait PlanOp: fmt::Display {
+    fn planned(&self) -> PlannedOp;
+}
+
+/// Prints planned changes as diff-like lines or as JSON.
+fn print_ops<T: PlanOp>(ops: &[T], json: bool) {
+    if json {
+        let planned: Vec<PlannedOp> = ops.iter().map(|op| op.planned()).collect();
+        println!("{}", serde_json::to_string_pretty(&planned).unwrap());
+    } else if ops.is_empty() {
+        println!("all records are up to date");
+    } else {
+        for op in ops {
+            println!("{}", op);
+        }
+    }
+}
+
+impl PlanOp for Op {
+    fn planned(&self) -> PlannedOp {
+        PlannedOp {
+            action: self.action.name().to_owned(),
+            name: self.record.name(),
+            kind: self.record.family.dns_type_name().to_owned(),
+            old: self.old.clone(),
+            new: self.new.clone(),
+        }
+    }
+}
 
-    let my_ip_addr = env::args()
-                         .nth(4)
-                         .or_else(|| get_my_ip_address().map(|v| v.to_string()))
-                         .unwrap();
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.action.describe(f,
+                             &*self.record.name(),
+                             self.record.family.dns_type_name(),
+                             self.old.as_ref(),
+                             self.new.as_ref())
+    }
+}
 
-    let mut yadns = YandexDNS::new(&*config.token);
-    let home_record = yadns.send(&ListRequest::new(&*config.domain))
-                           .unwrap()
-                           .records
-                           .into_iter()
-                           .find(|rec| rec.kind == DnsType::A && rec.subdomain == config.subdomain);
+/// Compares the record with what the DNS provider has, returns operations
+/// needed to point it to `ip`. Only one record is changed, other records
+/// of the same name and type (e.g. round-robin ones) are left alone.
+fn plan_record(record: &Record, ip: &str, force: bool) -> Result<Vec<Op>, String> {
+    let mut provider = try!(dns::from_config(&record.provider));
+    let mut existing: Vec<DnsRecord> = try!(provider.list(&*record.domain).map_err(|err| err.to_string()))
+                                           .into_iter()
+                                           .filter(|rec| {
+                                               rec.kind == record.family.dns_type_name() &&
+                                               rec.subdomain == record.subdomain
+                                           })
+                                           .collect();
 
-    match home_record {
+    // prefer the record which already points to us, if any
+    if let Some(pos) = existing.iter().position(|rec| rec.content == ip) {
+        let rec = existing.remove(pos);
+        existing.insert(0, rec);
+    }
+
+    let mut ops = Vec::new();
+    match existing.into_iter().next() {
         Some(rec) => {
-            yadns.send(rec.as_edit_req()
-                          .content(&*my_ip_addr))
-                 .unwrap();
+            // a TTL the provider didn't report is assumed to differ
+            let ttl_changed = record.ttl.map_or(false, |ttl| rec.ttl != Some(ttl));
+            if force || rec.content != ip || ttl_changed {
+                ops.push(Op {
+                    record: record.clone(),
+                    old: Some(rec.content.clone()),
+                    new: Some(ip.to_owned()),
+                    action: Action::Edit(rec),
+                });
+            }
         }
         None => {
-            yadns.send(AddRequest::new(DnsType::A, &*config.domain)
-                           .subdomain(&*config.subdomain)
-                           .content(&*my_ip_addr))
-                 .unwrap();
+            ops.push(Op {
+                record: record.clone(),
+                action: Action::Add,
+                old: None,
+                new: Some(ip.to_owned()),
+            })
+        }
+    }
+
+    Ok(ops)
+}
+
+/// Performs a planned action, `new` is the record to add or to replace the old one with.
+fn apply_action(provider: &mut DnsProvider,
+                domain: &str,
+                action: &Action,
+                new: Option<&DnsRecord>)
+                -> Result<(), String> {
+    let result = match (action, new) {
+        (&Action::Add, Some(new)) => provider.add(domain, new),
+        (&Action::Edit(ref old), Some(new)) => provider.edit(domain, old, new),
+        (&Action::Delete(ref old), _) => provider.delete(domain, old),
+        _ => return Err("no new record given".to_owned()),
+    };
+
+    result.map_err(|err| err.to_string())
+}
+
+fn apply_op(op: &Op) -> Result<(), String> {
+    let record = &op.record;
+    let mut provider = try!(dns::from_config(&record.provider));
+    let new = op.new.as_ref().map(|content| {
+        let mut new = DnsRecord::new(&*record.subdomain, record.family.dns_type_name(), &**content);
+        new.ttl = record.ttl;
+        new
+    });
+
+    apply_action(&mut *provider, &*record.domain, &op.action, new.as_ref())
+}
+
+/// Changes planned for records of one address family.
+struct Plan {
+    family: Family,
+    ip: String,
+    source: String,
+    ops: Vec<Op>,
+    failed: bool,
+}
+
+/// Detects current addresses and plans changes for records which point
+/// elsewhere. Records are always listed, so new ones are created even
+/// if the address didn't change since the last run.
+fn plan_records(records: &[Record], ip_sources: &[Box<IpSource>], force: bool) -> Vec<Plan> {
+    let mut plans = Vec::new();
+
+    for family in [Family::V4, Family::V6].iter().cloned() {
+        let family_records: Vec<&Record> = records.iter().filter(|rec| rec.family == family).collect();
+        if family_records.is_empty() {
+            continue;
+        }
+
+        let (my_ip_addr, source) = match ipsource::detect(ip_sources, family) {
+            Ok((ip, source)) => {
+                log(&format!("detected {} address {} via {}", family.dns_type_name(), ip, source));
+                (ip.to_string(), source)
+            }
+            Err(errors) => {
+                log(&format!("can't detect {} address:", family.dns_type_name()));
+                for err in errors {
+                    log(&format!("    {}", err));
+                }
+                plans.push(Plan {
+                    family: family,
+                    ip: String::new(),
+                    source: String::new(),
+                    ops: Vec::new(),
+                    failed: true,
+                });
+                continue;
+            }
+        };
+
+        let mut ops = Vec::new();
+        let mut failed = false;
+        for record in family_records {
+            match plan_record(record, &*my_ip_addr, force) {
+                Ok(record_ops) => ops.extend(record_ops),
+                Err(err) => {
+                    log(&format!("failed to list {}: {}", record.name(), err));
+                    failed = true;
+                }
+            }
+        }
+        plans.push(Plan {
+            family: family,
+            ip: my_ip_addr,
+            source: source,
+            ops: ops,
+            failed: failed,
+        });
+    }
+
+    plans
+}
+
+/// Applies planned changes, returns the list of changed records.
+fn update_records(records: &[Record], ip_sources: &[Box<IpSource>], force: bool) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for plan in plan_records(records, ip_sources, force) {
+        let Plan { family, ip: my_ip_addr, source, ops, mut failed } = plan;
+        let last_ip_addr = utils::state::read_state(family.last_ip_state())
+                               .map(|ip| ip.trim().to_owned())
+                               .unwrap_or_else(String::new);
+
+        for op in ops {
+            if let Err(err) = apply_op(&op) {
+                log(&format!("failed to update {}: {}", op.record.name(), err));
+                failed = true;
+                continue;
+            }
+            println!("{}", op);
+
+            changes.push(Change {
+                domain: op.record.domain.clone(),
+                name: op.record.name(),
+                family: family,
+                ip: my_ip_addr.clone(),
+                old_ip: op.old.unwrap_or_else(|| last_ip_addr.clone()),
+                source: source.clone(),
+                verified: None,
+            });
+        }
+
+        // try again next time if some records were not updated
+        if !failed {
+            save_last_ip(family, &*my_ip_addr);
+        }
+    }
+
+    record_history(&changes);
+    changes
+}
+
+const HISTORY_STATE: &'static str = "yadns/history";
+
+/// Appends changes to the history file, one tab separated line per record:
+/// timestamp, record name, type, old and new address, source strategy.
+fn record_history(changes: &[Change]) {
+    let timestamp = time::now_utc().rfc3339().to_string();
+    for change in changes {
+        let line = [&*timestamp,
+                    &*change.name,
+                    change.family.dns_type_name(),
+                    &*change.old_ip,
+                    &*change.ip,
+                    &*change.source]
+                       .join("\t");
+        if let Err(err) = utils::state::append_state(HISTORY_STATE, &*line) {
+            log(&format!("can't write history: {}", err));
+        }
+    }
+}
+
+fn read_history() -> Vec<HistoryEntry> {
+    utils::state::read_state(HISTORY_STATE)
+        .unwrap_or_else(String::new)
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 6 {
+                return None;
+            }
+            Some(HistoryEntry {
+                timestamp: fields[0].to_owned(),
+                record: fields[1].to_owned(),
+                kind: fields[2].to_owned(),
+                old: fields[3].to_owned(),
+                new: fields[4].to_owned(),
+                source: fields[5].to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// Prints the history of address changes as a table, CSV or JSON.
+fn print_history(csv: bool, json: bool) {
+    let entries = read_history();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries).unwrap());
+    } else if csv {
+        println!("timestamp,record,type,old,new,source");
+        for entry in &entries {
+            println!("{}",
+                     utils::csv::row(&[&*entry.timestamp,
+                                       &*entry.record,
+                                       &*entry.kind,
+                                       &*entry.old,
+                                       &*entry.new,
+                                       &*entry.source]));
+        }
+    } else {
+        for entry in &entries {
+            println!("{}  {} {}: {} -> {} (via {})",
+                     entry.timestamp,
+                     entry.record,
+                     entry.kind,
+                     if entry.old.is_empty() {
+                         "none"
+                     } else {
+                         &*entry.old
+                     },
+                     entry.new,
+                     entry.source);
+        }
+    }
+}
+
+const EXIT_IN_SYNC: i32 = 0;
+const EXIT_PENDING: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+
+/// Prints planned changes without applying them,
+/// returns exit code telling if there are pending changes.
+fn dry_run(records: &[Record], ip_sources: &[Box<IpSource>], force: bool, json: bool) -> i32 {
+    let plans = plan_records(records, ip_sources, force);
+    let failed = plans.iter().any(|plan| plan.failed);
+    let ops: Vec<Op> = plans.into_iter().flat_map(|plan| plan.ops.into_iter()).collect();
+
+    print_ops(&ops, json);
+
+    if failed {
+        EXIT_ERROR
+    } else if ops.is_empty() {
+        EXIT_IN_SYNC
+    } else {
+        EXIT_PENDING
+    }
+}
+
+/// Record types managed by `sync`, records of other types
+/// (including dynamic A and AAAA records) are never touched.
+const ZONE_TYPES: &'static [&'static str] = &["MX", "TXT", "CNAME", "SRV"];
+
+/// Checks if a record matches the protection list. Entries are either
+/// a subdomain (`@` for the domain itself) or a subdomain and a type,
+/// like `_dmarc TXT`.
+fn is_protected(protect: &[String], subdomain: &str, kind: &str) -> bool {
+    protect.iter().any(|entry| {
+        let mut parts = entry.split_whitespace();
+        parts.next() == Some(subdomain) && parts.next().map(|k| k == kind).unwrap_or(true)
+    })
+}
+
+/// Compares record contents ignoring the trailing dot of host names.
+fn same_content(a: &str, b: &str) -> bool {
+    a.trim_right_matches('.') == b.trim_right_matches('.')
+}
+
+/// Checks that the existing record has the attributes set in the zone file,
+/// attributes not set there are not compared.
+fn same_attrs(old: &DnsRecord, new: &DnsRecord) -> bool {
+    [(old.ttl, new.ttl), (old.priority, new.priority), (old.weight, new.weight), (old.port, new.port)]
+        .iter()
+        .all(|&(old, new)| new.is_none() || old == new)
+}
+
+/// Record content with its attributes, for diff output.
+fn describe_record(rec: &DnsRecord) -> String {
+    let attrs: Vec<String> = [("priority", rec.priority), ("weight", rec.weight), ("port", rec.port), ("ttl", rec.ttl)]
+                                 .iter()
+                                 .filter_map(|&(name, value)| value.map(|value| format!("{} {}", name, value)))
+                                 .collect();
+    if attrs.is_empty() {
+        rec.content.clone()
+    } else {
+        format!("{} ({})", rec.content, attrs.join(", "))
+    }
+}
+
+/// A planned change of a static zone record.
+struct ZoneOp {
+    name: String,
+    kind: &'static str,
+    action: Action,
+    old: Option<String>,
+    new: Option<DnsRecord>,
+}
+
+impl PlanOp for ZoneOp {
+    fn planned(&self) -> PlannedOp {
+        PlannedOp {
+            action: self.action.name().to_owned(),
+            name: self.name.clone(),
+            kind: self.kind.to_owned(),
+            old: self.old.clone(),
+            new: self.new.as_ref().map(describe_record),
+        }
+    }
+}
+
+impl fmt::Display for ZoneOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.action.describe(f,
+                             &*self.name,
+                             self.kind,
+                             self.old.as_ref(),
+                             self.new.as_ref().map(describe_record).as_ref())
+    }
+}
+
+/// Plans changes making zone records of managed types match the zone file.
+/// Records with the same content and attributes are kept, other existing
+/// records are edited in place, and the rest are deleted unless protected.
+fn plan_zone(zone: &ZoneConfig, mut records: Vec<DnsRecord>) -> Result<Vec<ZoneOp>, String> {
+    let wanted = zone.record.clone().unwrap_or_else(Vec::new);
+    let protect = zone.protect.clone().unwrap_or_else(Vec::new);
+
+    if let Some(rec) = wanted.iter().find(|rec| !ZONE_TYPES.contains(&&*rec.kind)) {
+        return Err(format!("unsupported record type {} for {}, expected one of {}",
+                           rec.kind,
+                           rec.subdomain,
+                           ZONE_TYPES.join(", ")));
+    }
+
+    let name = |subdomain: &str| {
+        if subdomain == "@" {
+            zone.domain.clone()
+        } else {
+            format!("{}.{}", subdomain, zone.domain)
+        }
+    };
+
+    let mut ops = Vec::new();
+    for &kind in ZONE_TYPES {
+        let (mut existing, rest): (Vec<DnsRecord>, Vec<DnsRecord>) =
+            records.into_iter().partition(|rec| rec.kind == kind);
+        records = rest;
+        let mut missing = Vec::new();
+
+        // records already in place need no changes
+        for rec in wanted.iter().filter(|rec| rec.kind == kind) {
+            let new = DnsRecord {
+                ttl: rec.ttl,
+                priority: rec.priority,
+                weight: rec.weight,
+                port: rec.port,
+                ..DnsRecord::new(&*rec.subdomain, kind, &*rec.content)
+            };
+            match existing.iter().position(|old| {
+                old.subdomain == new.subdomain && same_content(&*old.content, &*new.content) &&
+                same_attrs(old, &new)
+            }) {
+                Some(pos) => {
+                    existing.remove(pos);
+                }
+                None => missing.push(new),
+            }
+        }
+
+        for new in missing {
+            // prefer the record with the same content, when only attributes changed
+            let pos = existing.iter()
+                              .position(|old| {
+                                  old.subdomain == new.subdomain && same_content(&*old.content, &*new.content)
+                              })
+                              .or_else(|| existing.iter().position(|old| old.subdomain == new.subdomain));
+            let action = match pos {
+                Some(pos) => Action::Edit(existing.remove(pos)),
+                None => Action::Add,
+            };
+            ops.push(ZoneOp {
+                name: name(&*new.subdomain),
+                kind: kind,
+                old: match action {
+                    Action::Edit(ref old) => Some(describe_record(old)),
+                    _ => None,
+                },
+                action: action,
+                new: Some(new),
+            });
+        }
+
+        for old in existing {
+            if is_protected(&protect, &*old.subdomain, kind) {
+                log(&format!("keeping protected {} {} {}", name(&*old.subdomain), kind, old.content));
+                continue;
+            }
+            ops.push(ZoneOp {
+                name: name(&*old.subdomain),
+                kind: kind,
+                old: Some(describe_record(&old)),
+                action: Action::Delete(old),
+                new: None,
+            });
+        }
+    }
+
+    Ok(ops)
+}
+
+/// Makes static zone records match the zone file, returns exit code.
+fn sync_zone(config: &Config, path: &str, dry_run: bool, json: bool) -> i32 {
+    let zone = match utils::config::load_file::<ZoneConfig>(Path::new(path)) {
+        Ok(zone) => zone,
+        Err(err) => {
+            log(&err.to_string());
+            return err.exit_code();
+        }
+    };
+
+    let mut provider = match provider_config(config, zone.provider.as_ref(), zone.token.as_ref(), &*zone.domain)
+                                 .and_then(|provider| dns::from_config(&provider)) {
+        Ok(provider) => provider,
+        Err(err) => {
+            log(&format!("invalid {}: {}", path, err));
+            return utils::config::EXIT_CONFIG;
+        }
+    };
+
+    let records = match provider.list(&*zone.domain) {
+        Ok(records) => records,
+        Err(err) => {
+            log(&format!("failed to list {}: {}", zone.domain, err));
+            return EXIT_ERROR;
+        }
+    };
+
+    let ops = match plan_zone(&zone, records) {
+        Ok(ops) => ops,
+        Err(err) => {
+            log(&format!("invalid {}: {}", path, err));
+            return utils::config::EXIT_CONFIG;
+        }
+    };
+
+    if dry_run {
+        print_ops(&ops, json);
+        return if ops.is_empty() {
+            EXIT_IN_SYNC
+        } else {
+            EXIT_PENDING
+        };
+    }
+
+    let mut failed = false;
+    for op in &ops {
+        match apply_action(&mut *provider, &*zone.domain, &op.action, op.new.as_ref()) {
+            Ok(()) => println!("{}", op),
+            Err(err) => {
+                log(&format!("failed to {} {} {}: {}", op.action.name(), op.name, op.kind, err));
+                failed = true;
+            }
         }
     }
 
-    let push = PushMsg {
-        title: Some("New home IP address".into()),
-        // TODO: this clone is not really necessary most of time
-        body: Some((&*my_ip_addr).into()),
-        target: TargetIden::CurrentUser,
-        data: PushData::Note,
-        source_device_iden: pbcfg.device_iden,
+    if failed {
+        EXIT_ERROR
+    } else {
+        EXIT_IN_SYNC
+    }
+}
+
+/// Queries authoritative nameservers of the domain until all of them
+/// return the new address or the timeout passes, describes the result.
+fn verify_change(change: &Change, verify: &VerifyConfig) -> String {
+    let timeout = Duration::from_secs(verify.timeout.unwrap_or(120));
+    let interval = Duration::from_secs(verify.interval.unwrap_or(5));
+    let query_timeout = Duration::from_secs(3);
+    let qtype = match change.family {
+        Family::V4 => dns::TYPE_A,
+        Family::V6 => dns::TYPE_AAAA,
     };
 
-    match pbapi.send(&push) {
-        Ok(Push { iden, .. }) => println!("notified with push {}", iden),
+    let mut pending = match dns::authoritative_servers(&*change.domain, query_timeout) {
+        Ok(ref servers) if servers.is_empty() => return "not verified: nameservers not found".to_owned(),
+        Ok(servers) => servers,
+        Err(err) => return format!("not verified: {}", err),
+    };
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        pending.retain(|&(_, addr)| {
+            dns::query(addr, &*change.name, qtype, false, query_timeout)
+                .map(|answers| !answers.contains(&change.ip))
+                .unwrap_or(true)
+        });
+        if pending.is_empty() {
+            return "verified".to_owned();
+        }
+        if Instant::now() + interval > deadline || TERMINATED.load(Ordering::SeqCst) {
+            let hosts: Vec<&str> = pending.iter().map(|&(ref host, _)| &**host).collect();
+            return format!("not propagated to {} after {} s", hosts.join(", "), timeout.as_secs());
+        }
+        thread::sleep(interval);
+    }
+}
+
+/// Checks changed records if `[verify]` is configured. Records are checked
+/// concurrently, so this waits for the verify timeout at most once.
+fn verify_changes(config: &Config, changes: &mut [Change]) {
+    if let Some(ref verify) = config.verify {
+        let checks: Vec<thread::JoinHandle<String>> = changes.iter()
+                                                            .map(|change| {
+                                                                let change = change.clone();
+                                                                let verify = verify.clone();
+                                                                thread::spawn(move || verify_change(&change, &verify))
+                                                            })
+                                                            .collect();
+
+        for (change, check) in changes.iter_mut().zip(checks) {
+            let result = check.join().unwrap_or_else(|_| "not verified: check failed".to_owned());
+            log(&format!("{} {}: {}", change.name, change.family.dns_type_name(), result));
+            change.verified = Some(result);
+        }
+    }
+}
+
+/// Message parts for the changes: one `record type: old -> new` line
+/// per change, and all new and old addresses, comma separated.
+fn describe_changes(changes: &[Change]) -> (String, String, String) {
+    let summary = changes.iter()
+                         .map(|change| {
+                             format!("{} {}: {} -> {}{}",
+                                     change.name,
+                                     change.family.dns_type_name(),
+                                     if change.old_ip.is_empty() {
+                                         "none"
+                                     } else {
+                                         &*change.old_ip
+                                     },
+                                     change.ip,
+                                     change.verified
+                                           .as_ref()
+                                           .map(|verified| format!(" ({})", verified))
+                                           .unwrap_or_else(String::new))
+                         })
+                         .collect::<Vec<_>>()
+                         .join("\n");
+
+    let mut ips: Vec<&str> = Vec::new();
+    let mut old_ips: Vec<&str> = Vec::new();
+    for change in changes {
+        if !ips.contains(&&*change.ip) {
+            ips.push(&*change.ip);
+        }
+        if !change.old_ip.is_empty() && !old_ips.contains(&&*change.old_ip) {
+            old_ips.push(&*change.old_ip);
+        }
+    }
+
+    (summary, ips.join(", "), old_ips.join(", "))
+}
+
+fn notify_changes(config: &Config, changes: &[Change]) {
+    if changes.is_empty() {
+        return;
+    }
+
+    // [email] section in yadns/config.toml overrides notify/config.toml SMTP settings
+    let mut notifier = match NotifyConfig::load()
+                                 .map(|notify_config| match config.email.clone() {
+                                     Some(email) => notify_config.with_smtp(email),
+                                     None => notify_config,
+                                 })
+                                 .and_then(|notify_config| Chain::with_config(&notify_config)) {
+        Ok(notifier) => notifier,
         Err(err) => {
-            println!("push notification failed with error: {}", err);
-            println!("trying to send email...");
-            if let Ok(mut mailer) = SmtpTransportBuilder::localhost().map(|t| t.build()) {
-                if let Ok(email) = EmailBuilder::new()
-                                       .from("greybook@home.kstep.me")
-                                       .to(("me@kstep.me", "Master"))
-                                       .subject("New external IP address")
-                                       .body(&*format!("Hi, Master!
-
-Just for your \
-                                                        information, my new external IP \
-                                                        address is {}.
-
-Regards,
-Greybook.",
-                                                       my_ip_addr))
-                                       .build() {
-                    match mailer.send(email) {
-                        Ok(_) => println!("notified with email to me@kstep.me"),
-                        Err(err) => println!("email notification failed with error: {}", err),
+            log(&err.to_string());
+            return;
+        }
+    };
+
+    let (summary, ips, old_ips) = describe_changes(changes);
+    let template = Template::new("New home IP address", "{changes}")
+                       .email("New external IP address",
+                              "Hi, Master!\n\nJust for your information, my external IP \
+                               addresses have changed:\n\n{changes}\n\nRegards,\nGreybook.");
+    let previous = if old_ips.is_empty() {
+        "unknown"
+    } else {
+        &*old_ips
+    };
+
+    match notify::notify_event(&mut notifier,
+                               "new_ip",
+                               template,
+                               &[("ip", &*ips),
+                                 ("old_ip", &*old_ips),
+                                 ("previous", previous),
+                                 ("changes", &*summary)]) {
+        Ok(receipt) => log(&format!("notified with {}", receipt)),
+        Err(err) => log(&err.to_string()),
+    }
+}
+
+static TERMINATED: AtomicBool = ATOMIC_BOOL_INIT;
+
+extern "C" fn on_terminate(_: libc::c_int) {
+    TERMINATED.store(true, Ordering::SeqCst);
+}
+
+/// Starts `ip monitor` and sends a message for every address change it reports.
+fn watch_netlink(events: Sender<()>) -> io::Result<Child> {
+    let mut child = try!(Command::new("ip")
+                             .args(&["-o", "monitor", "address", "route"])
+                             .stdout(Stdio::piped())
+                             .spawn());
+
+    let stdout = child.stdout.take().unwrap();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            match line {
+                Ok(_) => {
+                    if events.send(()).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+
+    Ok(child)
+}
+
+/// Runs updates on every network change reported by netlink
+/// (if enabled), and every `interval` seconds anyway.
+fn daemon(config: &Config, records: &[Record], ip_sources: &[Box<IpSource>]) {
+    let daemon_config = config.daemon.clone().unwrap_or(DaemonConfig {
+        interval: None,
+        debounce: None,
+        netlink: None,
+    });
+    let interval = Duration::from_secs(daemon_config.interval.unwrap_or(300));
+    let debounce = Duration::from_secs(daemon_config.debounce.unwrap_or(5));
+    // wake up regularly to check if we were asked to terminate
+    let tick = Duration::from_secs(1);
+
+    unsafe {
+        libc::signal(libc::SIGTERM, on_terminate as libc::sighandler_t);
+        libc::signal(libc::SIGINT, on_terminate as libc::sighandler_t);
+    }
+
+    let (sender, events) = channel();
+    let mut monitor = if daemon_config.netlink.unwrap_or(true) {
+        match watch_netlink(sender) {
+            Ok(child) => Some(child),
+            Err(err) => {
+                log(&*format!("can't watch netlink events, polling only: {}", err));
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    log(&*format!("started, polling every {} seconds", interval.as_secs()));
+
+    let mut next_poll = Instant::now();
+    while !TERMINATED.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        let changed = if now >= next_poll {
+            true
+        } else {
+            match events.recv_timeout(cmp::min(next_poll - now, tick)) {
+                Ok(()) => {
+                    // wait until the link settles down, e.g. after ppp reconnect
+                    while !TERMINATED.load(Ordering::SeqCst) &&
+                          events.recv_timeout(debounce).is_ok() {
                     }
+                    log("network change detected");
+                    true
+                }
+                Err(RecvTimeoutError::Timeout) => false,
+                Err(RecvTimeoutError::Disconnected) => {
+                    if monitor.is_some() {
+                        log("ip monitor exited, polling only");
+                        monitor = None;
+                    }
+                    thread::sleep(cmp::min(next_poll - now, tick));
+                    false
                 }
             }
+        };
+
+        if changed && !TERMINATED.load(Ordering::SeqCst) {
+            let mut changes = update_records(records, ip_sources, false);
+            verify_changes(config, &mut changes);
+            notify_changes(config, &changes);
+            next_poll = Instant::now() + interval;
         }
     }
 
+    if let Some(mut child) = monitor {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+    log("terminated");
+}
+
+fn log(msg: &str) {
+    let _ = writeln!(io::stderr(), "yaddns: {}", msg);
+}
+
+fn main() {
+    let args = utils::config::strip_overrides(env::args());
+    let force = args.iter().any(|arg| arg == "--force");
+    let daemonize = args.iter().any(|arg| arg == "--daemon");
+    let dry = args.iter().any(|arg| arg == "--dry-run");
+    let json = args.iter().any(|arg| arg == "--json");
+    let csv = args.iter().any(|arg| arg == "--csv");
+    let positional: Vec<String> = args.into_iter().filter(|arg| !arg.starts_with("--")).collect();
+
+    // history is read from the state only, so it works without config
+    if positional.get(1).map(|arg| &**arg) == Some("history") {
+        print_history(csv, json);
+        return;
+    }
+
+    let config = utils::load_config_verbose::<Config>("yadns/config.toml");
+
+    if positional.get(1).map(|arg| &**arg) == Some("sync") {
+        match positional.get(2) {
+            Some(path) => exit(sync_zone(&config, path, dry, json)),
+            None => {
+                println!("usage: yaddns sync [--dry-run [--json]] <zone.toml>");
+                exit(utils::config::EXIT_USAGE);
+            }
+        }
+    }
+
+    let records = match config_records(&config) {
+        Ok(records) => records,
+        Err(err) => {
+            log(&format!("invalid yadns/config.toml: {}", err));
+            exit(utils::config::EXIT_CONFIG);
+        }
+    };
+
+    // ppp ip-up hook passes local IP address as the 4th argument
+    let arg_ip_addr = positional.into_iter().nth(4);
+
+    let ip_sources = match config.ip_source {
+        Some(ref sources) => {
+            match ipsource::from_config(sources, arg_ip_addr) {
+                Ok(sources) => sources,
+                Err(err) => {
+                    log(&format!("invalid yadns/config.toml: {}", err));
+                    exit(utils::config::EXIT_CONFIG);
+                }
+            }
+        }
+        None => ipsource::default_sources(arg_ip_addr),
+    };
+
+    if dry {
+        exit(dry_run(&records, &ip_sources, force, json));
+    } else if daemonize {
+        daemon(&config, &records, &ip_sources);
+    } else {
+        let mut changes = update_records(&records, &ip_sources, force);
+        verify_changes(&config, &mut changes);
+        notify_changes(&config, &changes);
+    }
+}
+
+#[test]
+fn test_is_protected() {
+    let protect = vec!["@".to_owned(), "_dmarc TXT".to_owned()];
+    assert!(is_protected(&protect, "@", "MX"));
+    assert!(is_protected(&protect, "_dmarc", "TXT"));
+    assert!(!is_protected(&protect, "_dmarc", "CNAME"));
+    assert!(!is_protected(&protect, "mail", "MX"));
+}
+
+#[test]
+fn test_same_content() {
+    assert!(same_content("mx.yandex.net.", "mx.yandex.net"));
+    assert!(!same_content("mx.yandex.net", "mx.yandex.ru"));
+}
+
+#[cfg(test)]
+fn zone_record(subdomain: &str, kind: &str, content: &str, priority: Option<u32>) -> ZoneRecordConfig {
+    ZoneRecordConfig {
+        subdomain: subdomain.to_owned(),
+        kind: kind.to_owned(),
+        content: content.to_owned(),
+        ttl: None,
+        priority: priority,
+        weight: None,
+        port: None,
+    }
+}
+
+#[test]
+fn test_plan_zone() {
+    let zone = ZoneConfig {
+        domain: "example.com".to_owned(),
+        token: None,
+        provider: None,
+        protect: Some(vec!["_dmarc TXT".to_owned()]),
+        record: Some(vec![zone_record("@", "MX", "mx.yandex.net.", Some(10)),
+                          zone_record("@", "TXT", "v=spf1 -all", None),
+                          zone_record("mail", "CNAME", "domain.mail.yandex.net", None),
+                          zone_record("www", "CNAME", "example.github.io", None)]),
+    };
+    let existing = vec![DnsRecord { priority: Some(20), ..DnsRecord::new("@", "MX", "mx.yandex.net") },
+                        DnsRecord { ttl: Some(300), ..DnsRecord::new("@", "TXT", "v=spf1 -all") },
+                        DnsRecord::new("mail", "CNAME", "old.example.net"),
+                        DnsRecord::new("ftp", "CNAME", "files.example.net"),
+                        DnsRecord::new("_dmarc", "TXT", "v=DMARC1; p=none"),
+                        DnsRecord::new("@", "A", "1.2.3.4")];
+
+    let ops: Vec<String> = plan_zone(&zone, existing).unwrap().iter().map(|op| op.to_string()).collect();
+    assert_eq!(ops,
+               vec!["~ example.com MX mx.yandex.net (priority 20) -> mx.yandex.net. (priority 10)",
+                    "~ mail.example.com CNAME old.example.net -> domain.mail.yandex.net",
+                    "+ www.example.com CNAME example.github.io",
+                    "- ftp.example.com CNAME files.example.net"]);
+
+    let zone = ZoneConfig { record: Some(vec![zone_record("@", "A", "1.2.3.4", None)]), ..zone };
+    assert!(plan_zone(&zone, vec![]).is_err());
+}
+
+#[test]
+fn test_describe_changes() {
+    let change = |name: &str, family: Family, old_ip: &str, ip: &str| {
+        Change {
+            domain: "example.com".to_owned(),
+            name: name.to_owned(),
+            family: family,
+            ip: ip.to_owned(),
+            old_ip: old_ip.to_owned(),
+            source: "dns".to_owned(),
+            verified: None,
+        }
+    };
+    let changes = vec![change("home.example.com", Family::V4, "10.0.0.1", "10.0.0.2"),
+                       change("www.example.com", Family::V4, "10.0.0.1", "10.0.0.2"),
+                       change("home.example.com", Family::V6, "", "2001:db8::1")];
+
+    let (summary, ips, old_ips) = describe_changes(&changes);
+    assert_eq!(summary,
+               "home.example.com A: 10.0.0.1 -> 10.0.0.2\n\
+                www.example.com A: 10.0.0.1 -> 10.0.0.2\n\
+                home.example.com AAAA: none -> 2001:db8::1");
+    assert_eq!(ips, "10.0.0.2, 2001:db8::1");
+    assert_eq!(old_ips, "10.0.0.1");
 }
@@ -0,0 +1,515 @@
+//! Layered configuration loading.
+//!
+//! A config file named like `yadns/config.toml` is assembled from several
+//! layers, each one overriding values from the previous ones:
+//!
+//! 1. system config dirs (`$XDG_CONFIG_DIRS`, `/etc/xdg` by default),
+//!    the least preferred dir first;
+//! 2. user config dir (`$XDG_CONFIG_HOME`, `~/.config` by default);
+//! 3. environment variables prefixed with the upper-cased config dir name,
+//!    e.g. `YADNS_TOKEN` sets `token`, `YADNS_EMAIL__FROM` sets `email.from`;
+//!    only nested keys and keys known to the config are taken, so unrelated
+//!    variables sharing the prefix (like `NOTIFY_SOCKET`) are ignored;
+//! 4. `--set <dir>.<key>=<value>` command line arguments,
+//!    e.g. `--set yadns.token=secret`.
+//!
+//! Tables are merged key by key, any other values (including arrays) are
+//! replaced as a whole. Values from environment and command line are taken
+//! as strings, unless the key is already set to a number, a boolean or an
+//! array by a lower layer, in which case the value is parsed as such.
+//!
+//! A config is not found unless there is a file or a `--set` override for it,
+//! environment variables alone don't make one.
+
+use serde::{de, Deserialize, Deserializer};
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use toml::{self, Table, Value};
+use xdg;
+
+// Exit codes from sysexits.h, so config problems can be told apart
+// from script-specific exit codes.
+pub const EXIT_USAGE: i32 = 64;
+pub const EXIT_NOINPUT: i32 = 66;
+pub const EXIT_IOERR: i32 = 74;
+pub const EXIT_CONFIG: i32 = 78;
+
+#[derive(Debug)]
+pub struct SyntaxError {
+    pub line: usize,
+    pub col: usize,
+    pub desc: String,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    NotFound {
+        filename: String,
+        searched: Vec<PathBuf>,
+    },
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Syntax {
+        path: PathBuf,
+        errors: Vec<SyntaxError>,
+    },
+    Decode {
+        paths: Vec<PathBuf>,
+        error: toml::DecodeError,
+    },
+    Override {
+        source: String,
+        reason: String,
+    },
+}
+
+impl ConfigError {
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            ConfigError::NotFound { .. } => EXIT_NOINPUT,
+            ConfigError::Io { .. } => EXIT_IOERR,
+            ConfigError::Syntax { .. } | ConfigError::Decode { .. } => EXIT_CONFIG,
+            ConfigError::Override { .. } => EXIT_USAGE,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::NotFound { ref filename, ref searched } => {
+                try!(write!(f, "config file {} not found, searched in:", filename));
+                for path in searched {
+                    try!(write!(f, "\n    {}", path.display()));
+                }
+                Ok(())
+            }
+            ConfigError::Io { ref path, ref error } => {
+                write!(f, "can't read config file {}: {}", path.display(), error)
+            }
+            ConfigError::Syntax { ref path, ref errors } => {
+                try!(write!(f, "syntax error in config file {}:", path.display()));
+                for err in errors {
+                    // parser reports 0-based positions, editors count from 1
+                    try!(write!(f, "\n    {}:{}: {}", err.line + 1, err.col + 1, err.desc));
+                }
+                Ok(())
+            }
+            ConfigError::Decode { ref paths, ref error } => {
+                try!(write!(f, "invalid config: {}", error));
+                if !paths.is_empty() {
+                    try!(write!(f, "\nloaded from:"));
+                    for path in paths {
+                        try!(write!(f, "\n    {}", path.display()));
+                    }
+                }
+                Ok(())
+            }
+            ConfigError::Override { ref source, ref reason } => {
+                write!(f, "invalid config override {}: {}", source, reason)
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn description(&self) -> &str {
+        match *self {
+            ConfigError::NotFound { .. } => "config file not found",
+            ConfigError::Io { .. } => "config file read error",
+            ConfigError::Syntax { .. } => "config file syntax error",
+            ConfigError::Decode { .. } => "config file decode error",
+            ConfigError::Override { .. } => "invalid config override",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            ConfigError::Io { ref error, .. } => Some(error),
+            ConfigError::Decode { ref error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Builder for a layered config, see module docs for the layers order.
+pub struct ConfigLoader {
+    filename: String,
+    env_prefix: Option<String>,
+    overrides: Vec<String>,
+}
+
+impl ConfigLoader {
+    /// Creates a loader with environment prefix derived from
+    /// the config dir name and overrides taken from process arguments.
+    pub fn new(filename: &str) -> ConfigLoader {
+        ConfigLoader {
+            filename: filename.to_owned(),
+            env_prefix: filename.split('/')
+                                .next()
+                                .and_then(|dir| if dir != filename {
+                                    Some(dir.to_uppercase().replace('-', "_"))
+                                } else {
+                                    None
+                                }),
+            overrides: env::args().skip(1).collect(),
+        }
+    }
+
+    pub fn env_prefix(mut self, prefix: Option<&str>) -> ConfigLoader {
+        self.env_prefix = prefix.map(|p| p.to_owned());
+        self
+    }
+
+    /// Sets command line arguments to look for `--set` overrides in.
+    pub fn args<I: IntoIterator<Item = String>>(mut self, args: I) -> ConfigLoader {
+        self.overrides = args.into_iter().collect();
+        self
+    }
+
+    /// Config dirs in the order they are merged, least preferred first.
+    pub fn search_paths(&self) -> Vec<PathBuf> {
+        let mut dirs = xdg::get_config_dirs();
+        dirs.reverse();
+        dirs.extend(xdg::get_config_home().ok());
+        dirs.into_iter().map(|p| p.join(&self.filename)).collect()
+    }
+
+    pub fn load_table(&self) -> Result<(Table, Vec<PathBuf>), ConfigError> {
+        self.load_layers(&[])
+    }
+
+    /// Merges all layers, environment variables are taken for keys
+    /// in `fields`, keys set by files, and nested keys only.
+    fn load_layers(&self, fields: &[&str]) -> Result<(Table, Vec<PathBuf>), ConfigError> {
+        let searched = self.search_paths();
+        let mut table = Table::new();
+        let mut found = Vec::new();
+
+        for path in searched.iter().filter(|p| p.exists()) {
+            merge_tables(&mut table, try!(read_table(path)));
+            found.push(path.clone());
+        }
+
+        if let Some(ref prefix) = self.env_prefix {
+            let prefix = format!("{}_", prefix);
+            for (name, value) in env::vars().filter(|&(ref name, _)| name.starts_with(&*prefix)) {
+                let key = name[prefix.len()..].to_lowercase().replace("__", ".");
+                if !key.contains('.') && !table.contains_key(&key) && !fields.contains(&&*key) {
+                    continue;
+                }
+                try!(set_value(&mut table, &key, &value).map_err(|reason| {
+                    ConfigError::Override {
+                        source: format!("environment variable {}", name),
+                        reason: reason,
+                    }
+                }));
+            }
+        }
+
+        let mut overridden = false;
+        let scope = format!("{}.", self.filename.split('/').next().unwrap_or(""));
+        for (key, value) in try!(parse_overrides(&self.overrides)) {
+            if key.starts_with(&*scope) {
+                try!(set_value(&mut table, &key[scope.len()..], &value).map_err(|reason| {
+                    ConfigError::Override {
+                        source: format!("--set {}={}", key, value),
+                        reason: reason,
+                    }
+                }));
+                overridden = true;
+            }
+        }
+
+        if found.is_empty() && !overridden {
+            return Err(ConfigError::NotFound {
+                filename: self.filename.clone(),
+                searched: searched,
+            });
+        }
+
+        Ok((table, found))
+    }
+
+    pub fn load<C: Deserialize>(&self) -> Result<C, ConfigError> {
+        let (table, paths) = try!(self.load_layers(field_names::<C>()));
+        Deserialize::deserialize(&mut toml::Decoder::new(Value::Table(table))).map_err(|error| {
+            ConfigError::Decode {
+                paths: paths,
+                error: error,
+            }
+        })
+    }
+
+    /// Same as `load`, but reports the error to stderr and exits
+    /// with the error's exit code, so scripts don't have to.
+    pub fn load_verbose<C: Deserialize>(&self) -> C {
+        match self.load() {
+            Ok(config) => config,
+            Err(err) => {
+                let _ = writeln!(io::stderr(), "{}", err);
+                exit(err.exit_code());
+            }
+        }
+    }
+}
+
+/// Deserializer which only records the field names a struct asks for.
+struct FieldNames(&'static [&'static str]);
+
+impl Deserializer for FieldNames {
+    type Error = toml::DecodeError;
+
+    fn deserialize<V: de::Visitor>(&mut self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: de::Visitor>(&mut self,
+                                          _name: &'static str,
+                                          fields: &'static [&'static str],
+                                          _visitor: V)
+                                          -> Result<V::Value, Self::Error> {
+        self.0 = fields;
+        Err(de::Error::custom("field names only"))
+    }
+}
+
+/// Top level keys of config `C`, empty if it's not a struct.
+fn field_names<C: Deserialize>() -> &'static [&'static str] {
+    let mut names = FieldNames(&[]);
+    let _ = C::deserialize(&mut names);
+    names.0
+}
+
+/// Loads a single standalone file given by path, without any layering.
+pub fn load_file<C: Deserialize>(path: &Path) -> Result<C, ConfigError> {
+    let table = try!(read_table(path));
+    Deserialize::deserialize(&mut toml::Decoder::new(Value::Table(table))).map_err(|error| {
+        ConfigError::Decode {
+            paths: vec![path.to_path_buf()],
+            error: error,
+        }
+    })
+}
+
+fn read_table(path: &Path) -> Result<Table, ConfigError> {
+    let mut buf = String::new();
+    if let Err(error) = File::open(path).and_then(|mut f| f.read_to_string(&mut buf)) {
+        return Err(ConfigError::Io {
+            path: path.to_path_buf(),
+            error: error,
+        });
+    }
+
+    let mut parser = toml::Parser::new(&buf);
+    match parser.parse() {
+        Some(table) => Ok(table),
+        None => {
+            let errors = parser.errors
+                               .iter()
+                               .map(|e| {
+                                   let (line, col) = parser.to_linecol(e.lo);
+                                   SyntaxError {
+                                       line: line,
+                                       col: col,
+                                       desc: e.desc.clone(),
+                                   }
+                               })
+                               .collect();
+            Err(ConfigError::Syntax {
+                path: path.to_path_buf(),
+                errors: errors,
+            })
+        }
+    }
+}
+
+fn merge_tables(base: &mut Table, layer: Table) {
+    for (key, value) in layer {
+        let both_tables = match (base.get(&key), &value) {
+            (Some(&Value::Table(_)), &Value::Table(_)) => true,
+            _ => false,
+        };
+
+        if both_tables {
+            if let Value::Table(other) = value {
+                if let Some(&mut Value::Table(ref mut inner)) = base.get_mut(&key) {
+                    merge_tables(inner, other);
+                }
+            }
+        } else {
+            base.insert(key, value);
+        }
+    }
+}
+
+/// Sets dotted `key` in `table` to `raw` value, creating intermediate
+/// tables as needed and coercing the value to the type of the old one.
+fn set_value(table: &mut Table, key: &str, raw: &str) -> Result<(), String> {
+    let mut parts = key.splitn(2, '.');
+    let head = parts.next().unwrap_or("");
+    if head.is_empty() {
+        return Err("empty key".to_owned());
+    }
+
+    match parts.next() {
+        Some(rest) => {
+            let entry = table.entry(head.to_owned()).or_insert_with(|| Value::Table(Table::new()));
+            match *entry {
+                Value::Table(ref mut inner) => set_value(inner, rest, raw),
+                ref other => Err(format!("{} is a {}, not a table", head, other.type_str())),
+            }
+        }
+        None => {
+            let value = try!(coerce_value(table.get(head), raw));
+            table.insert(head.to_owned(), value);
+            Ok(())
+        }
+    }
+}
+
+fn coerce_value(old: Option<&Value>, raw: &str) -> Result<Value, String> {
+    match old {
+        Some(&Value::Integer(_)) => {
+            raw.parse().map(Value::Integer).map_err(|_| format!("{:?} is not an integer", raw))
+        }
+        Some(&Value::Float(_)) => {
+            raw.parse().map(Value::Float).map_err(|_| format!("{:?} is not a float", raw))
+        }
+        Some(&Value::Boolean(_)) => {
+            raw.parse().map(Value::Boolean).map_err(|_| format!("{:?} is not a boolean", raw))
+        }
+        Some(&Value::Array(_)) => {
+            toml::Parser::new(&*format!("value = {}", raw))
+                .parse()
+                .and_then(|mut t| t.remove("value"))
+                .ok_or_else(|| format!("{:?} is not an array", raw))
+        }
+        _ => Ok(Value::String(raw.to_owned())),
+    }
+}
+
+/// Extracts `--set key=value` pairs from command line arguments.
+pub fn parse_overrides(args: &[String]) -> Result<Vec<(String, String)>, ConfigError> {
+    let mut result = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg != "--set" {
+            continue;
+        }
+
+        let pair = match args.next() {
+            Some(pair) => pair,
+            None => {
+                return Err(ConfigError::Override {
+                    source: "--set".to_owned(),
+                    reason: "missing key=value argument".to_owned(),
+                })
+            }
+        };
+
+        match pair.find('=') {
+            Some(idx) => result.push((pair[..idx].to_owned(), pair[idx + 1..].to_owned())),
+            None => {
+                return Err(ConfigError::Override {
+                    source: format!("--set {}", pair),
+                    reason: "expected key=value".to_owned(),
+                })
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Strips `--set key=value` pairs from command line arguments,
+/// so scripts can look at their own arguments only.
+pub fn strip_overrides<I: IntoIterator<Item = String>>(args: I) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--set" {
+            args.next();
+        } else {
+            result.push(arg);
+        }
+    }
+    result
+}
+
+pub fn load_config<C: Deserialize>(filename: &str) -> Result<C, ConfigError> {
+    ConfigLoader::new(filename).load()
+}
+
+pub fn load_config_verbose<C: Deserialize>(filename: &str) -> C {
+    ConfigLoader::new(filename).load_verbose()
+}
+
+#[test]
+fn test_merge_tables() {
+    let mut base = toml::Parser::new("a = 1\n[t]\nb = 2\nc = [1, 2]").parse().unwrap();
+    let layer = toml::Parser::new("[t]\nc = [3]\nd = \"x\"").parse().unwrap();
+    merge_tables(&mut base, layer);
+
+    let base = Value::Table(base);
+    assert_eq!(base.lookup("a"), Some(&Value::Integer(1)));
+    assert_eq!(base.lookup("t.b"), Some(&Value::Integer(2)));
+    assert_eq!(base.lookup("t.c"), Some(&Value::Array(vec![Value::Integer(3)])));
+    assert_eq!(base.lookup("t.d"), Some(&Value::String("x".to_owned())));
+}
+
+#[test]
+fn test_set_value() {
+    let mut table = toml::Parser::new("port = 25\n[email]\nto = [\"a\"]").parse().unwrap();
+    set_value(&mut table, "port", "587").unwrap();
+    set_value(&mut table, "token", "12345").unwrap();
+    set_value(&mut table, "email.to", "[\"b\", \"c\"]").unwrap();
+    set_value(&mut table, "email.auth.user", "me").unwrap();
+    assert!(set_value(&mut table, "port", "smtp").is_err());
+    assert!(set_value(&mut table, "token.inner", "x").is_err());
+
+    let table = Value::Table(table);
+    assert_eq!(table.lookup("port"), Some(&Value::Integer(587)));
+    assert_eq!(table.lookup("token"), Some(&Value::String("12345".to_owned())));
+    assert_eq!(table.lookup("email.to.1"), Some(&Value::String("c".to_owned())));
+    assert_eq!(table.lookup("email.auth.user"), Some(&Value::String("me".to_owned())));
+}
+
+#[test]
+fn test_parse_overrides() {
+    let args: Vec<String> = vec!["credit", "--set", "yadns.token=a=b", "x"]
+                                .into_iter()
+                                .map(|s| s.to_owned())
+                                .collect();
+    assert_eq!(parse_overrides(&args).unwrap(),
+               vec![("yadns.token".to_owned(), "a=b".to_owned())]);
+    assert_eq!(strip_overrides(args), vec!["credit".to_owned(), "x".to_owned()]);
+    assert!(parse_overrides(&["--set".to_owned(), "key".to_owned()]).is_err());
+}
+
+#[test]
+fn test_env_overrides() {
+    let loader = ConfigLoader::new("scripts-test-env/config.toml")
+                     .env_prefix(Some("SCRIPTS_TEST_ENV"))
+                     .args(vec!["--set".to_owned(), "scripts-test-env.port=25".to_owned()]);
+    env::set_var("SCRIPTS_TEST_ENV_SOCKET", "/run/systemd/notify");
+    env::set_var("SCRIPTS_TEST_ENV_TOKEN", "secret");
+    env::set_var("SCRIPTS_TEST_ENV_EMAIL__FROM", "me");
+
+    let table = Value::Table(loader.load_layers(&["token"]).unwrap().0);
+    assert_eq!(table.lookup("socket"), None);
+    assert_eq!(table.lookup("token"), Some(&Value::String("secret".to_owned())));
+    assert_eq!(table.lookup("email.from"), Some(&Value::String("me".to_owned())));
+    assert_eq!(table.lookup("port"), Some(&Value::String("25".to_owned())));
+
+    match loader.args(Vec::new()).load_layers(&["token"]) {
+        Err(ConfigError::NotFound { .. }) => {}
+        other => panic!("expected NotFound, got {:?}", other.map(|(table, _)| table)),
+    }
+}
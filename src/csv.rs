@@ -0,0 +1,22 @@
+//! Minimal CSV output for exporting script data.
+
+/// Quotes a field if it contains separators, quotes or line breaks.
+pub fn field(value: &str) -> String {
+    if value.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Formats a single CSV line, without the line break.
+pub fn row<S: AsRef<str>>(fields: &[S]) -> String {
+    fields.iter().map(|value| field(value.as_ref())).collect::<Vec<_>>().join(",")
+}
+
+#[test]
+fn test_row() {
+    assert_eq!(row(&["a", "b c", "d,e", "say \"hi\""]),
+               "a,b c,\"d,e\",\"say \"\"hi\"\"\"");
+    assert_eq!(row::<&str>(&[]), "");
+}
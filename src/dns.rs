@@ -0,0 +1,752 @@
+//! DNS hosting providers able to list and change zone records.
+//!
+//! Providers are configured as named tables, records refer to them by name:
+//!
+//! ```toml
+//! [provider.yandex]
+//! type = "yandex"      # Yandex PDD API
+//! token = "..."
+//!
+//! [provider.home]
+//! type = "rfc2136"     # dynamic updates with nsupdate, zone listing with AXFR
+//! server = "ns1.example.com"
+//! port = 53            # optional
+//! key_file = "/etc/bind/yaddns.key"  # either a key file...
+//! key_name = "yaddns"  # ...or an inline TSIG key
+//! key_algorithm = "hmac-sha256"      # optional, hmac-sha256 by default
+//! key_secret = "base64=="
+//! ttl = 300            # optional, TTL for added records without one
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use yadns::{self, YandexDNS, ListRequest, AddRequest, DnsType};
+
+include!(concat!(env!("OUT_DIR"), "/dns.rs"));
+
+/// Provider independent DNS record, `subdomain` is `@` for the domain itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DnsRecord {
+    pub subdomain: String,
+    pub kind: String,
+    pub content: String,
+    pub ttl: Option<u32>,
+    pub priority: Option<u32>,
+    pub weight: Option<u32>,
+    pub port: Option<u32>,
+}
+
+impl DnsRecord {
+    pub fn new<S: Into<String>, K: Into<String>, C: Into<String>>(subdomain: S, kind: K, content: C) -> DnsRecord {
+        DnsRecord {
+            subdomain: subdomain.into(),
+            kind: kind.into(),
+            content: content.into(),
+            ttl: None,
+            priority: None,
+            weight: None,
+            port: None,
+        }
+    }
+
+    /// Fully qualified name of the record, with the trailing dot.
+    pub fn fqdn(&self, domain: &str) -> String {
+        if self.subdomain == "@" {
+            format!("{}.", domain)
+        } else {
+            format!("{}.{}.", self.subdomain, domain)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DnsError {
+    pub provider: String,
+    pub message: String,
+}
+
+impl DnsError {
+    fn new<S: Into<String>, M: fmt::Display>(provider: S, message: M) -> DnsError {
+        DnsError {
+            provider: provider.into(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.provider, self.message)
+    }
+}
+
+impl Error for DnsError {
+    fn description(&self) -> &str {
+        "DNS provider request failed"
+    }
+}
+
+pub type DnsResult<T> = Result<T, DnsError>;
+
+pub trait DnsProvider {
+    /// Short description of the provider for logs.
+    fn name(&self) -> String;
+    fn list(&mut self, domain: &str) -> DnsResult<Vec<DnsRecord>>;
+    fn add(&mut self, domain: &str, record: &DnsRecord) -> DnsResult<()>;
+    fn edit(&mut self, domain: &str, old: &DnsRecord, new: &DnsRecord) -> DnsResult<()>;
+    fn delete(&mut self, domain: &str, record: &DnsRecord) -> DnsResult<()>;
+}
+
+/// Yandex PDD DNS API.
+pub struct Yandex {
+    token: String,
+    // edits and deletes are done on records as returned by the API
+    listed: Option<(String, Vec<yadns::DnsRecord>)>,
+}
+
+impl Yandex {
+    pub fn new<S: Into<String>>(token: S) -> Yandex {
+        Yandex {
+            token: token.into(),
+            listed: None,
+        }
+    }
+
+    fn list_raw(&mut self, domain: &str) -> DnsResult<()> {
+        if self.listed.as_ref().map(|&(ref listed, _)| listed == domain).unwrap_or(false) {
+            return Ok(());
+        }
+
+        let records = try!(YandexDNS::new(&*self.token)
+                               .send(&ListRequest::new(domain))
+                               .map_err(|err| DnsError::new(self.name(), format!("{:?}", err))))
+                          .records;
+        self.listed = Some((domain.to_owned(), records));
+        Ok(())
+    }
+
+    /// Finds the API record matching `record` in the last listing.
+    fn find(&self, domain: &str, record: &DnsRecord) -> DnsResult<&yadns::DnsRecord> {
+        self.listed
+            .as_ref()
+            .and_then(|&(_, ref records)| {
+                records.iter().find(|rec| {
+                    rec.subdomain == record.subdomain && yandex_kind(&rec.kind) == record.kind &&
+                    rec.content == record.content
+                })
+            })
+            .ok_or_else(|| {
+                DnsError::new(self.name(),
+                              format!("no {} record {} in {}", record.kind, record.subdomain, domain))
+            })
+    }
+}
+
+fn yandex_kind(kind: &DnsType) -> String {
+    format!("{:?}", kind)
+}
+
+fn yandex_type(kind: &str) -> Option<DnsType> {
+    match kind {
+        "A" => Some(DnsType::A),
+        "AAAA" => Some(DnsType::AAAA),
+        "CNAME" => Some(DnsType::CNAME),
+        "MX" => Some(DnsType::MX),
+        "NS" => Some(DnsType::NS),
+        "SRV" => Some(DnsType::SRV),
+        "TXT" => Some(DnsType::TXT),
+        _ => None,
+    }
+}
+
+fn from_yandex(rec: &yadns::DnsRecord) -> DnsRecord {
+    DnsRecord {
+        ttl: Some(rec.ttl),
+        priority: rec.priority,
+        weight: rec.weight,
+        port: rec.port,
+        ..DnsRecord::new(&*rec.subdomain, yandex_kind(&rec.kind), &*rec.content)
+    }
+}
+
+macro_rules! set_record_fields {
+    ($req:expr, $rec:expr) => {{
+        $req.content(&*$rec.content);
+        if let Some(ttl) = $rec.ttl {
+            $req.ttl(ttl);
+        }
+        if let Some(priority) = $rec.priority {
+            $req.priority(priority);
+        }
+        if let Some(weight) = $rec.weight {
+            $req.weight(weight);
+        }
+        if let Some(port) = $rec.port {
+            $req.port(port);
+        }
+    }}
+}
+
+impl DnsProvider for Yandex {
+    fn name(&self) -> String {
+        "yandex".to_owned()
+    }
+
+    fn list(&mut self, domain: &str) -> DnsResult<Vec<DnsRecord>> {
+        self.listed = None;
+        try!(self.list_raw(domain));
+        Ok(self.listed
+               .as_ref()
+               .map(|&(_, ref records)| records.iter().map(from_yandex).collect())
+               .unwrap_or_else(Vec::new))
+    }
+
+    fn add(&mut self, domain: &str, record: &DnsRecord) -> DnsResult<()> {
+        let kind = try!(yandex_type(&*record.kind).ok_or_else(|| {
+            DnsError::new(self.name(), format!("unsupported record type {}", record.kind))
+        }));
+        let mut req = AddRequest::new(kind, domain);
+        req.subdomain(&*record.subdomain);
+        set_record_fields!(req, record);
+
+        self.listed = None;
+        YandexDNS::new(&*self.token)
+            .send(&req)
+            .map(|_| ())
+            .map_err(|err| DnsError::new(self.name(), format!("{:?}", err)))
+    }
+
+    fn edit(&mut self, domain: &str, old: &DnsRecord, new: &DnsRecord) -> DnsResult<()> {
+        try!(self.list_raw(domain));
+        let result = {
+            let mut req = try!(self.find(domain, old)).as_edit_req();
+            set_record_fields!(req, new);
+            YandexDNS::new(&*self.token).send(&req).map(|_| ())
+        };
+
+        self.listed = None;
+        result.map_err(|err| DnsError::new(self.name(), format!("{:?}", err)))
+    }
+
+    fn delete(&mut self, domain: &str, record: &DnsRecord) -> DnsResult<()> {
+        try!(self.list_raw(domain));
+        let result = {
+            let req = try!(self.find(domain, record)).as_delete_req();
+            YandexDNS::new(&*self.token).send(&req).map(|_| ())
+        };
+
+        self.listed = None;
+        result.map_err(|err| DnsError::new(self.name(), format!("{:?}", err)))
+    }
+}
+
+/// TSIG key to sign dynamic updates and zone transfers with.
+pub enum TsigKey {
+    File(String),
+    Inline {
+        algorithm: String,
+        name: String,
+        secret: String,
+    },
+}
+
+impl TsigKey {
+    /// Arguments for `nsupdate` and `dig`, which take keys the same way.
+    fn args(&self) -> Vec<String> {
+        match *self {
+            TsigKey::File(ref path) => vec!["-k".to_owned(), path.clone()],
+            TsigKey::Inline { ref algorithm, ref name, ref secret } => {
+                vec!["-y".to_owned(), format!("{}:{}:{}", algorithm, name, secret)]
+            }
+        }
+    }
+}
+
+/// RFC 2136 dynamic updates with `nsupdate`, records are listed
+/// with a zone transfer (`dig AXFR`), which must be allowed for the key.
+pub struct Rfc2136 {
+    pub server: String,
+    pub port: u16,
+    pub key: Option<TsigKey>,
+    pub ttl: u32,
+}
+
+impl Rfc2136 {
+    fn key_args(&self) -> Vec<String> {
+        self.key.as_ref().map(|key| key.args()).unwrap_or_else(Vec::new)
+    }
+
+    /// Sends update commands for the zone in a single transaction.
+    fn update(&self, domain: &str, commands: &[String]) -> DnsResult<()> {
+        let mut script = format!("server {} {}\nzone {}.\n", self.server, self.port, domain);
+        for command in commands {
+            script.push_str(&*command);
+            script.push('\n');
+        }
+        script.push_str("send\n");
+
+        let mut child = try!(Command::new("nsupdate")
+                                 .args(&self.key_args())
+                                 .stdin(Stdio::piped())
+                                 .stdout(Stdio::piped())
+                                 .stderr(Stdio::piped())
+                                 .spawn()
+                                 .map_err(|err| DnsError::new(self.name(), format!("can't run nsupdate: {}", err))));
+        if let Some(ref mut stdin) = child.stdin {
+            try!(stdin.write_all(script.as_bytes()).map_err(|err| DnsError::new(self.name(), err)));
+        }
+        // close stdin so nsupdate finishes
+        child.stdin.take();
+
+        let output = try!(child.wait_with_output().map_err(|err| DnsError::new(self.name(), err)));
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(DnsError::new(self.name(), String::from_utf8_lossy(&output.stderr).trim()))
+        }
+    }
+
+    fn add_command(&self, domain: &str, record: &DnsRecord) -> String {
+        format!("update add {} {} {} {}",
+                record.fqdn(domain),
+                record.ttl.unwrap_or(self.ttl),
+                record.kind,
+                rdata(record))
+    }
+}
+
+fn delete_command(domain: &str, record: &DnsRecord) -> String {
+    format!("update delete {} {} {}", record.fqdn(domain), record.kind, rdata(record))
+}
+
+/// Record data in master file format.
+fn rdata(record: &DnsRecord) -> String {
+    match &*record.kind {
+        "MX" => format!("{} {}", record.priority.unwrap_or(10), record.content),
+        "SRV" => {
+            format!("{} {} {} {}",
+                    record.priority.unwrap_or(0),
+                    record.weight.unwrap_or(0),
+                    record.port.unwrap_or(0),
+                    record.content)
+        }
+        "TXT" => {
+            // strings longer than 255 bytes must be split
+            let bytes = record.content.as_bytes();
+            bytes.chunks(255)
+                 .map(|chunk| {
+                     format!("\"{}\"",
+                             String::from_utf8_lossy(chunk).replace('\\', "\\\\").replace('"', "\\\""))
+                 })
+                 .collect::<Vec<_>>()
+                 .join(" ")
+        }
+        _ => record.content.clone(),
+    }
+}
+
+/// Joins quoted character strings of TXT record data.
+fn unquote_txt(data: &str) -> String {
+    let mut result = String::new();
+    let mut quoted = false;
+    let mut chars = data.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' if quoted => result.extend(chars.next()),
+            c if quoted => result.push(c),
+            _ => (),
+        }
+    }
+    result
+}
+
+/// Splits the first whitespace separated field off.
+fn split_field(line: &str) -> (&str, &str) {
+    let line = line.trim_left();
+    let end = line.find(char::is_whitespace).unwrap_or(line.len());
+    line.split_at(end)
+}
+
+/// Parses `dig +noall +answer` output, like
+/// `www.example.com.  300  IN  A  1.2.3.4`.
+fn parse_dig_output(domain: &str, output: &str) -> Vec<DnsRecord> {
+    let suffix = format!(".{}", domain);
+    output.lines()
+          .filter_map(|line| {
+              let (name, rest) = split_field(line);
+              let (ttl, rest) = split_field(rest);
+              let (class, rest) = split_field(rest);
+              let (kind, data) = split_field(rest);
+              let data = data.trim();
+              if name.starts_with(';') || class != "IN" || kind.is_empty() {
+                  return None;
+              }
+
+              let name = name.trim_right_matches('.');
+              let subdomain = if name == domain {
+                  "@".to_owned()
+              } else if name.ends_with(&*suffix) {
+                  name[..name.len() - suffix.len()].to_owned()
+              } else {
+                  return None;
+              };
+
+              let mut record = DnsRecord::new(subdomain, kind, data);
+              record.ttl = ttl.parse().ok();
+              let parts: Vec<&str> = data.split_whitespace().collect();
+              match kind {
+                  "MX" if parts.len() == 2 => {
+                      record.priority = parts[0].parse().ok();
+                      record.content = parts[1].to_owned();
+                  }
+                  "SRV" if parts.len() == 4 => {
+                      record.priority = parts[0].parse().ok();
+                      record.weight = parts[1].parse().ok();
+                      record.port = parts[2].parse().ok();
+                      record.content = parts[3].to_owned();
+                  }
+                  "TXT" => record.content = unquote_txt(data),
+                  _ => (),
+              }
+              Some(record)
+          })
+          .collect()
+}
+
+impl DnsProvider for Rfc2136 {
+    fn name(&self) -> String {
+        format!("rfc2136 {}", self.server)
+    }
+
+    fn list(&mut self, domain: &str) -> DnsResult<Vec<DnsRecord>> {
+        let output = try!(Command::new("dig")
+                              .args(&self.key_args())
+                              .arg(format!("@{}", self.server))
+                              .args(&["-p", &*self.port.to_string(), domain, "AXFR", "+noall", "+answer"])
+                              .output()
+                              .map_err(|err| DnsError::new(self.name(), format!("can't run dig: {}", err))));
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        // dig reports refused transfers on stdout and still exits with 0
+        if !output.status.success() || stdout.contains("Transfer failed") {
+            return Err(DnsError::new(self.name(),
+                                     format!("zone transfer failed: {}{}",
+                                             stdout.trim(),
+                                             String::from_utf8_lossy(&output.stderr).trim())));
+        }
+
+        Ok(parse_dig_output(domain, &*stdout))
+    }
+
+    fn add(&mut self, domain: &str, record: &DnsRecord) -> DnsResult<()> {
+        self.update(domain, &[self.add_command(domain, record)])
+    }
+
+    fn edit(&mut self, domain: &str, old: &DnsRecord, new: &DnsRecord) -> DnsResult<()> {
+        self.update(domain,
+                    &[delete_command(domain, old), self.add_command(domain, new)])
+    }
+
+    fn delete(&mut self, domain: &str, record: &DnsRecord) -> DnsResult<()> {
+        self.update(domain, &[delete_command(domain, record)])
+    }
+}
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_NS: u16 = 2;
+pub const TYPE_AAAA: u16 = 28;
+
+fn build_query(id: u16, name: &str, qtype: u16, recursive: bool) -> Vec<u8> {
+    let mut msg = vec![(id >> 8) as u8, id as u8, if recursive { 1 } else { 0 }, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in name.trim_right_matches('.').split('.').filter(|label| !label.is_empty()) {
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+    msg.extend_from_slice(&[(qtype >> 8) as u8, qtype as u8, 0, 1]);
+    msg
+}
+
+fn read_u16(msg: &[u8], pos: usize) -> Option<u16> {
+    if pos + 2 <= msg.len() {
+        Some((msg[pos] as u16) << 8 | msg[pos + 1] as u16)
+    } else {
+        None
+    }
+}
+
+/// Reads a possibly compressed domain name, returns it
+/// with the position right after the name.
+fn read_name(msg: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+
+    // limit jumps, so pointer loops can't hang us
+    for _ in 0..128 {
+        let len = match msg.get(pos) {
+            Some(&len) => len as usize,
+            None => return None,
+        };
+
+        if len == 0 {
+            return Some((labels.join("."), end.unwrap_or(pos + 1)));
+        } else if len & 0xc0 == 0xc0 {
+            let target = match read_u16(msg, pos) {
+                Some(ptr) => (ptr & 0x3fff) as usize,
+                None => return None,
+            };
+            end = end.or(Some(pos + 2));
+            pos = target;
+        } else {
+            if pos + 1 + len > msg.len() {
+                return None;
+            }
+            labels.push(String::from_utf8_lossy(&msg[pos + 1..pos + 1 + len]).into_owned());
+            pos += 1 + len;
+        }
+    }
+    None
+}
+
+/// Extracts answers of the requested type from a response:
+/// addresses for A and AAAA, host names for NS.
+fn parse_response(msg: &[u8], qtype: u16) -> Result<Vec<String>, String> {
+    let truncated = || "truncated response".to_owned();
+    if msg.len() < 12 {
+        return Err(truncated());
+    }
+
+    match msg[3] & 0x0f {
+        0 => (),
+        // NXDOMAIN, the record is not there (yet)
+        3 => return Ok(Vec::new()),
+        rcode => return Err(format!("server returned error code {}", rcode)),
+    }
+
+    let questions = read_u16(msg, 4).unwrap_or(0);
+    let answers = read_u16(msg, 6).unwrap_or(0);
+    let mut pos = 12;
+
+    for _ in 0..questions {
+        pos = try!(read_name(msg, pos).ok_or_else(&truncated)).1 + 4;
+    }
+
+    let mut result = Vec::new();
+    for _ in 0..answers {
+        pos = try!(read_name(msg, pos).ok_or_else(&truncated)).1;
+        let rtype = try!(read_u16(msg, pos).ok_or_else(&truncated));
+        let len = try!(read_u16(msg, pos + 8).ok_or_else(&truncated)) as usize;
+        let data = pos + 10;
+        if data + len > msg.len() {
+            return Err(truncated());
+        }
+        let rdata = &msg[data..data + len];
+
+        match (rtype, len) {
+            (TYPE_A, 4) if qtype == TYPE_A => {
+                result.push(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]).to_string())
+            }
+            (TYPE_AAAA, 16) if qtype == TYPE_AAAA => {
+                let segment = |i: usize| (rdata[i * 2] as u16) << 8 | rdata[i * 2 + 1] as u16;
+                result.push(Ipv6Addr::new(segment(0),
+                                          segment(1),
+                                          segment(2),
+                                          segment(3),
+                                          segment(4),
+                                          segment(5),
+                                          segment(6),
+                                          segment(7))
+                                .to_string())
+            }
+            (TYPE_NS, _) if qtype == TYPE_NS => {
+                result.push(try!(read_name(msg, data).ok_or_else(&truncated)).0)
+            }
+            _ => (),
+        }
+        pos = data + len;
+    }
+
+    Ok(result)
+}
+
+/// Sends a single DNS query over UDP and waits for the answer.
+/// Non-recursive queries are meant for authoritative servers.
+pub fn query(server: SocketAddr,
+             name: &str,
+             qtype: u16,
+             recursive: bool,
+             timeout: Duration)
+             -> Result<Vec<String>, String> {
+    let bind = match server {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    };
+    let socket = try!(UdpSocket::bind(bind).map_err(|err| err.to_string()));
+    try!(socket.set_read_timeout(Some(timeout)).map_err(|err| err.to_string()));
+
+    let id = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos() as u16).unwrap_or(0);
+    try!(socket.send_to(&build_query(id, name, qtype, recursive), server)
+               .map_err(|err| err.to_string()));
+
+    let mut buf = [0u8; 4096];
+    loop {
+        let (len, from) = try!(socket.recv_from(&mut buf)
+                                     .map_err(|err| format!("no answer from {}: {}", server, err)));
+        // ignore stray packets, e.g. late answers to previous queries
+        if from == server && read_u16(&buf, 0) == Some(id) {
+            return parse_response(&buf[..len], qtype);
+        }
+    }
+}
+
+/// The first nameserver from `/etc/resolv.conf`, Yandex public DNS if there is none.
+pub fn system_resolver() -> SocketAddr {
+    let mut conf = String::new();
+    let _ = File::open("/etc/resolv.conf").and_then(|mut f| f.read_to_string(&mut conf));
+
+    conf.lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("nameserver"), Some(addr)) => addr.parse::<IpAddr>().ok(),
+                _ => None,
+            }
+        })
+        .next()
+        .map(|addr| SocketAddr::new(addr, 53))
+        .unwrap_or_else(|| "77.88.8.8:53".parse().unwrap())
+}
+
+/// Authoritative nameservers of a domain with their addresses.
+pub fn authoritative_servers(domain: &str, timeout: Duration) -> Result<Vec<(String, SocketAddr)>, String> {
+    let hosts = try!(query(system_resolver(), domain, TYPE_NS, true, timeout));
+    if hosts.is_empty() {
+        return Err(format!("no NS records for {}", domain));
+    }
+
+    let mut servers = Vec::new();
+    for host in hosts {
+        if let Some(addr) = (&*host, 53).to_socket_addrs().ok().and_then(|mut addrs| addrs.next()) {
+            servers.push((host, addr));
+        }
+    }
+    Ok(servers)
+}
+
+pub fn from_config(config: &ProviderConfig) -> Result<Box<DnsProvider>, String> {
+    let required = |value: &Option<String>, key: &str| {
+        value.clone().ok_or_else(|| format!("{} provider requires {}", config.kind, key))
+    };
+
+    Ok(match &*config.kind {
+        "yandex" => Box::new(Yandex::new(try!(required(&config.token, "token")))) as Box<DnsProvider>,
+        "rfc2136" => {
+            let key = match (&config.key_file, &config.key_name) {
+                (&Some(ref path), _) => Some(TsigKey::File(path.clone())),
+                (&None, &Some(ref name)) => {
+                    Some(TsigKey::Inline {
+                        algorithm: config.key_algorithm.clone().unwrap_or_else(|| "hmac-sha256".to_owned()),
+                        name: name.clone(),
+                        secret: try!(required(&config.key_secret, "key_secret")),
+                    })
+                }
+                (&None, &None) => None,
+            };
+            Box::new(Rfc2136 {
+                server: try!(required(&config.server, "server")),
+                port: config.port.unwrap_or(53),
+                key: key,
+                ttl: config.ttl.unwrap_or(300),
+            }) as Box<DnsProvider>
+        }
+        other => return Err(format!("unknown DNS provider type {}", other)),
+    })
+}
+
+/// Yandex provider for configs with just an API token.
+pub fn yandex_config(token: String) -> ProviderConfig {
+    ProviderConfig {
+        kind: "yandex".to_owned(),
+        token: Some(token),
+        server: None,
+        port: None,
+        key_file: None,
+        key_name: None,
+        key_secret: None,
+        key_algorithm: None,
+        ttl: None,
+    }
+}
+
+#[test]
+fn test_rdata() {
+    use std::iter;
+
+    let mut mx = DnsRecord::new("@", "MX", "mx.yandex.net.");
+    mx.priority = Some(20);
+    assert_eq!(rdata(&mx), "20 mx.yandex.net.");
+
+    let txt = DnsRecord::new("@", "TXT", "v=spf1 include:_spf.yandex.net \"quoted\" ~all");
+    assert_eq!(rdata(&txt), r#""v=spf1 include:_spf.yandex.net \"quoted\" ~all""#);
+    assert_eq!(unquote_txt(&*rdata(&txt)), txt.content);
+
+    let long = DnsRecord::new("mail._domainkey", "TXT", iter::repeat('k').take(300).collect::<String>());
+    assert_eq!(unquote_txt(&*rdata(&long)), long.content);
+    assert_eq!(rdata(&long).matches('"').count(), 4);
+}
+
+#[test]
+fn test_parse_dig_output() {
+    let output = "example.com.\t3600\tIN\tSOA\tns1.example.com. admin.example.com. 1 3600 600 86400 300\n\
+                  example.com.\t3600\tIN\tMX\t10 mx.yandex.net.\n\
+                  home.example.com.\t300\tIN\tA\t1.2.3.4\n\
+                  _xmpp-client._tcp.example.com. 300 IN SRV 0 5 5222 xmpp.example.com.\n\
+                  example.com.\t300\tIN\tTXT\t\"v=spf1 \" \"-all\"\n\
+                  ;; Query time: 1 msec\n";
+    let records = parse_dig_output("example.com", output);
+    assert_eq!(records.len(), 5);
+
+    assert_eq!(records[1].subdomain, "@");
+    assert_eq!(records[1].content, "mx.yandex.net.");
+    assert_eq!(records[1].priority, Some(10));
+
+    assert_eq!(records[2], DnsRecord { ttl: Some(300), ..DnsRecord::new("home", "A", "1.2.3.4") });
+
+    assert_eq!(records[3].subdomain, "_xmpp-client._tcp");
+    assert_eq!(records[3].port, Some(5222));
+    assert_eq!(records[3].content, "xmpp.example.com.");
+
+    assert_eq!(records[4].content, "v=spf1 -all");
+}
+
+#[test]
+fn test_parse_response() {
+    let mut msg = build_query(0x1234, "home.example.com", TYPE_A, false);
+    // answer count and QR flag
+    msg[2] |= 0x80;
+    msg[7] = 2;
+    // compressed pointer to the question name
+    msg.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 1, 44, 0, 4, 1, 2, 3, 4]);
+    msg.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 1, 44, 0, 2, 0xc0, 12]);
+    assert_eq!(parse_response(&msg, TYPE_A), Ok(vec!["1.2.3.4".to_owned()]));
+
+    let mut msg = build_query(1, "example.com", TYPE_NS, true);
+    msg[7] = 1;
+    msg.extend_from_slice(&[0xc0, 12, 0, 2, 0, 1, 0, 0, 1, 44, 0, 6, 3, b'n', b's', b'1', 0xc0, 12]);
+    assert_eq!(parse_response(&msg, TYPE_NS), Ok(vec!["ns1.example.com".to_owned()]));
+
+    // NXDOMAIN
+    msg[3] = 3;
+    assert_eq!(parse_response(&msg, TYPE_NS), Ok(vec![]));
+
+    // pointer loop
+    let mut msg = build_query(1, "example.com", TYPE_NS, true);
+    msg[7] = 1;
+    msg.extend_from_slice(&[0xc0, 29]);
+    assert!(parse_response(&msg, TYPE_NS).is_err());
+}
@@ -0,0 +1,614 @@
+//! Strategies to find out our own external IP address.
+//!
+//! Strategies are configured as an ordered list, the first one to return
+//! an address of the requested family wins:
+//!
+//! ```toml
+//! [[ip_source]]
+//! type = "argument"    # address passed on command line, e.g. by ppp ip-up hook
+//!
+//! [[ip_source]]
+//! type = "interface"   # global address of a network interface
+//! interface = "ppp0"
+//!
+//! [[ip_source]]
+//! type = "http"        # "what is my IP" service
+//! url = "https://api.ipify.org?format=json"
+//! json_field = "ip"    # optional, plain text response is expected if not set
+//! family = "ipv4"      # optional, restricts source to one family
+//!
+//! [[ip_source]]
+//! type = "upnp"        # ask router via UPnP IGD, IPv4 only
+//! location = "http://192.168.1.1:5000/rootDesc.xml"  # optional, SSDP discovery if not set
+//!
+//! [[ip_source]]
+//! type = "natpmp"      # ask router via NAT-PMP, IPv4 only
+//! gateway = "192.168.1.1"
+//!
+//! [[ip_source]]
+//! type = "socket"      # source address of a socket to public DNS resolver
+//! ```
+//!
+//! `timeout` (in ms) can be set for network strategies, 3 seconds by default.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::process::Command;
+use std::str;
+use std::time::Duration;
+use hyper::client::Client;
+use hyper::header::{ContentType, Headers};
+use regex::Regex;
+use serde_json;
+use url::{Url, UrlParser};
+
+include!(concat!(env!("OUT_DIR"), "/ipsource.rs"));
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    pub fn matches(&self, addr: &IpAddr) -> bool {
+        match (*self, *addr) {
+            (Family::V4, IpAddr::V4(_)) | (Family::V6, IpAddr::V6(_)) => true,
+            _ => false,
+        }
+    }
+
+    fn parse(name: &str) -> Option<Family> {
+        match name {
+            "ipv4" | "4" => Some(Family::V4),
+            "ipv6" | "6" => Some(Family::V6),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct IpSourceError {
+    pub source: String,
+    pub message: String,
+}
+
+impl IpSourceError {
+    fn new<S: Into<String>, M: fmt::Display>(source: S, message: M) -> IpSourceError {
+        IpSourceError {
+            source: source.into(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for IpSourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.source, self.message)
+    }
+}
+
+impl Error for IpSourceError {
+    fn description(&self) -> &str {
+        "IP address detection failed"
+    }
+}
+
+pub type IpResult = Result<IpAddr, IpSourceError>;
+
+pub trait IpSource {
+    /// Short description of the strategy for logs and history.
+    fn name(&self) -> String;
+    fn get(&self, family: Family) -> IpResult;
+}
+
+fn unsupported(name: String, family: Family) -> IpResult {
+    Err(IpSourceError::new(name, format!("{:?} is not supported", family)))
+}
+
+fn parse_ip(source: &str, text: &str) -> IpResult {
+    text.trim().parse().map_err(|_| IpSourceError::new(source, format!("invalid address {:?}", text.trim())))
+}
+
+/// Address given explicitly, e.g. on command line.
+pub struct Explicit(pub Option<String>);
+
+impl IpSource for Explicit {
+    fn name(&self) -> String {
+        "argument".to_owned()
+    }
+
+    fn get(&self, family: Family) -> IpResult {
+        match self.0 {
+            Some(ref addr) => {
+                let addr = try!(parse_ip(&*self.name(), addr));
+                match addr {
+                    // ppp ipv6-up hook passes link-local address, useless for DNS
+                    IpAddr::V6(ip) if ip.segments()[0] & 0xffc0 == 0xfe80 => {
+                        Err(IpSourceError::new(self.name(), format!("{} is link-local", ip)))
+                    }
+                    _ if family.matches(&addr) => Ok(addr),
+                    _ => unsupported(self.name(), family),
+                }
+            }
+            None => Err(IpSourceError::new(self.name(), "no address given")),
+        }
+    }
+}
+
+/// Global scope address of a network interface, as reported by `ip addr`.
+pub struct Interface(pub String);
+
+impl IpSource for Interface {
+    fn name(&self) -> String {
+        format!("interface {}", self.0)
+    }
+
+    fn get(&self, family: Family) -> IpResult {
+        let output = try!(Command::new("ip")
+                              .arg(if family == Family::V4 { "-4" } else { "-6" })
+                              .args(&["-o", "addr", "show", "dev", &*self.0, "scope", "global"])
+                              .output()
+                              .map_err(|err| IpSourceError::new(self.name(), err)));
+        if !output.status.success() {
+            return Err(IpSourceError::new(self.name(), String::from_utf8_lossy(&output.stderr)));
+        }
+
+        parse_ip_addr_output(&*String::from_utf8_lossy(&output.stdout))
+            .ok_or_else(|| IpSourceError::new(self.name(), "no global address found"))
+    }
+}
+
+/// Takes the first address from `ip -o addr show` output, like
+/// `3: ppp0    inet 1.2.3.4 peer 10.0.0.1/32 scope global ppp0`.
+fn parse_ip_addr_output(output: &str) -> Option<IpAddr> {
+    output.lines()
+          .filter_map(|line| {
+              let mut words = line.split_whitespace().skip_while(|w| *w != "inet" && *w != "inet6");
+              words.nth(1).and_then(|addr| addr.split('/').next()).and_then(|addr| addr.parse().ok())
+          })
+          .next()
+}
+
+/// "What is my IP" HTTP service, returning either plain text
+/// address or JSON object with the address in `json_field`.
+pub struct Http {
+    pub url: String,
+    pub json_field: Option<String>,
+    pub family: Option<Family>,
+    pub timeout: Duration,
+}
+
+impl IpSource for Http {
+    fn name(&self) -> String {
+        format!("http {}", self.url)
+    }
+
+    fn get(&self, family: Family) -> IpResult {
+        if self.family.map(|f| f != family).unwrap_or(false) {
+            return unsupported(self.name(), family);
+        }
+
+        let body = try!(http_get(&*self.url, self.timeout)
+                            .map_err(|err| IpSourceError::new(self.name(), err)));
+
+        let addr = match self.json_field {
+            Some(ref field) => {
+                let json: serde_json::Value = try!(serde_json::from_str(&*body)
+                                                       .map_err(|err| IpSourceError::new(self.name(), err)));
+                let value = json.lookup(&**field).and_then(|v| v.as_string()).map(|v| v.to_owned());
+                try!(value.ok_or_else(|| IpSourceError::new(self.name(), format!("no {} field", field))))
+            }
+            None => body,
+        };
+
+        let addr = try!(parse_ip(&*self.name(), &*addr));
+        if family.matches(&addr) {
+            Ok(addr)
+        } else {
+            unsupported(self.name(), family)
+        }
+    }
+}
+
+fn http_client(timeout: Duration) -> Client {
+    let mut client = Client::new();
+    client.set_read_timeout(Some(timeout));
+    client.set_write_timeout(Some(timeout));
+    client
+}
+
+fn http_get(url: &str, timeout: Duration) -> Result<String, String> {
+    let mut resp = try!(http_client(timeout).get(url).send().map_err(|err| err.to_string()));
+    if !resp.status.is_success() {
+        return Err(format!("HTTP status {}", resp.status));
+    }
+
+    let mut body = String::new();
+    try!(resp.read_to_string(&mut body).map_err(|err| err.to_string()));
+    Ok(body)
+}
+
+const WAN_SERVICES: [&'static str; 2] = ["urn:schemas-upnp-org:service:WANIPConnection:1",
+                                         "urn:schemas-upnp-org:service:WANPPPConnection:1"];
+
+/// Router's external address via UPnP Internet Gateway Device protocol.
+pub struct Upnp {
+    pub location: Option<String>,
+    pub timeout: Duration,
+}
+
+impl Upnp {
+    /// Finds IGD description URL with SSDP multicast search.
+    fn discover(&self) -> io::Result<String> {
+        let socket = try!(UdpSocket::bind("0.0.0.0:0"));
+        try!(socket.set_read_timeout(Some(self.timeout)));
+
+        for service in WAN_SERVICES.iter() {
+            let request = format!("M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \
+                                   \"ssdp:discover\"\r\nMX: 2\r\nST: {}\r\n\r\n",
+                                  service);
+            try!(socket.send_to(request.as_bytes(), "239.255.255.250:1900"));
+
+            let mut buf = [0u8; 2048];
+            if let Ok((len, _)) = socket.recv_from(&mut buf) {
+                let response = String::from_utf8_lossy(&buf[..len]);
+                let location = response.lines()
+                                       .filter_map(|line| {
+                                           let mut parts = line.splitn(2, ':');
+                                           match (parts.next(), parts.next()) {
+                                               (Some(name), Some(value))
+                                                   if name.to_lowercase() == "location" => {
+                                                   Some(value.trim().to_owned())
+                                               }
+                                               _ => None,
+                                           }
+                                       })
+                                       .next();
+                if let Some(location) = location {
+                    return Ok(location);
+                }
+            }
+        }
+
+        Err(io::Error::new(io::ErrorKind::NotFound, "no UPnP gateway found"))
+    }
+}
+
+/// Finds WAN connection service type and control URL in IGD description.
+fn find_control_url(description: &str) -> Option<(&'static str, String)> {
+    let service_re = Regex::new(r"(?s)<service>(.*?)</service>").unwrap();
+    let control_re = Regex::new(r"<controlURL>\s*([^<]+?)\s*</controlURL>").unwrap();
+
+    for service in service_re.captures_iter(description).filter_map(|c| c.at(1)) {
+        for kind in WAN_SERVICES.iter() {
+            if service.contains(&*format!("<serviceType>{}</serviceType>", kind)) {
+                if let Some(url) = control_re.captures(service).and_then(|c| c.at(1)) {
+                    return Some((*kind, url.to_owned()));
+                }
+            }
+        }
+    }
+    None
+}
+
+impl IpSource for Upnp {
+    fn name(&self) -> String {
+        "upnp".to_owned()
+    }
+
+    fn get(&self, family: Family) -> IpResult {
+        if family != Family::V4 {
+            return unsupported(self.name(), family);
+        }
+        let error = |err: String| IpSourceError::new(self.name(), err);
+
+        let location = match self.location {
+            Some(ref location) => location.clone(),
+            None => try!(self.discover().map_err(|err| error(err.to_string()))),
+        };
+        let description = try!(http_get(&*location, self.timeout).map_err(|err| error(err)));
+
+        let (service, control_url) = try!(find_control_url(&*description).ok_or_else(|| {
+            error("no WAN connection service found".to_owned())
+        }));
+        let base_url = try!(Url::parse(&*location).map_err(|err| error(err.to_string())));
+        let control_url = try!(UrlParser::new()
+                                   .base_url(&base_url)
+                                   .parse(&*control_url)
+                                   .map_err(|err| error(err.to_string())));
+
+        let body = format!("<?xml version=\"1.0\"?>\r\n<s:Envelope \
+                            xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+                            s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+                            <s:Body><u:GetExternalIPAddress xmlns:u=\"{}\"/></s:Body></s:Envelope>",
+                           service);
+        let mut headers = Headers::new();
+        headers.set(ContentType("text/xml; charset=\"utf-8\"".parse().unwrap()));
+        headers.set_raw("SOAPAction",
+                        vec![format!("\"{}#GetExternalIPAddress\"", service).into_bytes()]);
+
+        let mut resp = try!(http_client(self.timeout)
+                                .post(control_url)
+                                .headers(headers)
+                                .body(&*body)
+                                .send()
+                                .map_err(|err| error(err.to_string())));
+        let mut response = String::new();
+        try!(resp.read_to_string(&mut response).map_err(|err| error(err.to_string())));
+
+        let addr_re = Regex::new(r"<NewExternalIPAddress>\s*([^<]+?)\s*</NewExternalIPAddress>").unwrap();
+        let addr = try!(addr_re.captures(&*response)
+                               .and_then(|c| c.at(1))
+                               .ok_or_else(|| error(format!("unexpected response: {}", response))));
+        parse_ip(&*self.name(), addr)
+    }
+}
+
+/// Router's external address via NAT-PMP (RFC 6886).
+pub struct NatPmp {
+    pub gateway: String,
+    pub timeout: Duration,
+}
+
+impl IpSource for NatPmp {
+    fn name(&self) -> String {
+        format!("natpmp {}", self.gateway)
+    }
+
+    fn get(&self, family: Family) -> IpResult {
+        if family != Family::V4 {
+            return unsupported(self.name(), family);
+        }
+        let error = |err: io::Error| IpSourceError::new(self.name(), err);
+
+        let gateway = match self.gateway.parse::<SocketAddr>() {
+            Ok(addr) => addr,
+            Err(_) => {
+                let ip: Ipv4Addr = try!(self.gateway.parse().map_err(|_| {
+                    IpSourceError::new(self.name(), "invalid gateway address")
+                }));
+                SocketAddr::new(IpAddr::V4(ip), 5351)
+            }
+        };
+
+        let socket = try!(UdpSocket::bind("0.0.0.0:0").map_err(&error));
+        try!(socket.set_read_timeout(Some(self.timeout)).map_err(&error));
+        // version 0, opcode 0: public address request
+        try!(socket.send_to(&[0, 0], gateway).map_err(&error));
+
+        let mut buf = [0u8; 16];
+        let (len, _) = try!(socket.recv_from(&mut buf).map_err(&error));
+        parse_natpmp_response(&buf[..len]).map_err(|err| IpSourceError::new(self.name(), err))
+    }
+}
+
+fn parse_natpmp_response(buf: &[u8]) -> IpResult {
+    if buf.len() < 12 || buf[0] != 0 || buf[1] != 128 {
+        return Err(IpSourceError::new("natpmp", "malformed response"));
+    }
+
+    match (buf[2] as u16) << 8 | buf[3] as u16 {
+        0 => Ok(IpAddr::V4(Ipv4Addr::new(buf[8], buf[9], buf[10], buf[11]))),
+        code => Err(IpSourceError::new("natpmp", format!("result code {}", code))),
+    }
+}
+
+/// Source address of a socket connected to a public DNS resolver.
+/// Returns LAN address if we're behind NAT.
+pub struct Socket;
+
+impl IpSource for Socket {
+    fn name(&self) -> String {
+        "socket".to_owned()
+    }
+
+    fn get(&self, family: Family) -> IpResult {
+        // Connecting a UDP socket doesn't send any packets to the resolver,
+        // but makes kernel pick the source address used for outgoing traffic.
+        let (local, resolver) = match family {
+            Family::V4 => ("0.0.0.0:0", "8.8.8.8:53"),
+            Family::V6 => ("[::]:0", "[2001:4860:4860::8888]:53"),
+        };
+        UdpSocket::bind(local)
+            .and_then(|s| s.connect(resolver).and_then(|_| s.local_addr()))
+            .map(|addr| addr.ip())
+            .map_err(|err| IpSourceError::new(self.name(), err))
+    }
+}
+
+/// Builds strategies from config, `argument` is the address
+/// given on command line, if any.
+pub fn from_config(configs: &[IpSourceConfig],
+                   argument: Option<String>)
+                   -> Result<Vec<Box<IpSource>>, String> {
+    configs.iter()
+           .map(|config| {
+               let timeout = Duration::from_millis(config.timeout.unwrap_or(3000));
+               let family = match config.family {
+                   Some(ref name) => {
+                       Some(try!(Family::parse(name).ok_or_else(|| format!("unknown family {}", name))))
+                   }
+                   None => None,
+               };
+               let required = |value: &Option<String>, key: &str| {
+                   value.clone().ok_or_else(|| format!("{} IP source requires {}", config.kind, key))
+               };
+
+               Ok(match &*config.kind {
+                   "argument" => Box::new(Explicit(argument.clone())) as Box<IpSource>,
+                   "interface" => {
+                       Box::new(Interface(try!(required(&config.interface, "interface")))) as Box<IpSource>
+                   }
+                   "http" => {
+                       Box::new(Http {
+                           url: try!(required(&config.url, "url")),
+                           json_field: config.json_field.clone(),
+                           family: family,
+                           timeout: timeout,
+                       }) as Box<IpSource>
+                   }
+                   "upnp" => {
+                       Box::new(Upnp {
+                           location: config.location.clone(),
+                           timeout: timeout,
+                       }) as Box<IpSource>
+                   }
+                   "natpmp" => {
+                       Box::new(NatPmp {
+                           gateway: try!(required(&config.gateway, "gateway")),
+                           timeout: timeout,
+                       }) as Box<IpSource>
+                   }
+                   "socket" => Box::new(Socket) as Box<IpSource>,
+                   other => return Err(format!("unknown IP source type {}", other)),
+               })
+           })
+           .collect()
+}
+
+/// Default strategies: command line argument, then socket source address.
+pub fn default_sources(argument: Option<String>) -> Vec<Box<IpSource>> {
+    vec![Box::new(Explicit(argument)), Box::new(Socket)]
+}
+
+/// Tries sources in order, returns the first found address
+/// with the name of the source it came from.
+pub fn detect(sources: &[Box<IpSource>], family: Family) -> Result<(IpAddr, String), Vec<IpSourceError>> {
+    let mut errors = Vec::new();
+    for source in sources {
+        match source.get(family) {
+            Ok(addr) => return Ok((addr, source.name())),
+            Err(err) => errors.push(err),
+        }
+    }
+    Err(errors)
+}
+
+/// Serves canned HTTP responses for `count` requests, returns server address.
+#[cfg(test)]
+fn http_stand_in(responses: Vec<(&'static str, String)>) -> SocketAddr {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for (content_type, body) in responses {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.to_lowercase().starts_with("content-length:") {
+                    content_length = line[15..].trim().parse().unwrap();
+                }
+                if line == "\r\n" || line.is_empty() {
+                    break;
+                }
+            }
+            let mut request_body = vec![0u8; content_length];
+            reader.read_exact(&mut request_body).unwrap();
+
+            let mut stream = stream;
+            write!(stream,
+                   "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: \
+                    close\r\n\r\n{}",
+                   content_type,
+                   body.len(),
+                   body)
+                .unwrap();
+        }
+    });
+    addr
+}
+
+#[test]
+fn test_parse_ip_addr_output() {
+    assert_eq!(parse_ip_addr_output("3: ppp0    inet 1.2.3.4 peer 10.0.0.1/32 scope global \
+                                     ppp0\\       valid_lft forever preferred_lft forever\n"),
+               Some("1.2.3.4".parse().unwrap()));
+    assert_eq!(parse_ip_addr_output("2: eth0    inet6 2001:db8::1/64 scope global dynamic\n"),
+               Some("2001:db8::1".parse().unwrap()));
+    assert_eq!(parse_ip_addr_output(""), None);
+}
+
+#[test]
+fn test_http_source() {
+    let addr = http_stand_in(vec![("text/plain", "1.2.3.4\n".to_owned()),
+                                  ("application/json", r#"{"ip": "2001:db8::1"}"#.to_owned())]);
+
+    let plain = Http {
+        url: format!("http://{}/", addr),
+        json_field: None,
+        family: None,
+        timeout: Duration::from_millis(1000),
+    };
+    assert_eq!(plain.get(Family::V4).unwrap(), "1.2.3.4".parse::<IpAddr>().unwrap());
+
+    let json = Http { json_field: Some("ip".to_owned()), ..plain };
+    assert_eq!(json.get(Family::V6).unwrap(), "2001:db8::1".parse::<IpAddr>().unwrap());
+
+    let json = Http { family: Some(Family::V6), ..json };
+    assert!(json.get(Family::V4).is_err());
+}
+
+#[test]
+fn test_upnp_source() {
+    let description = format!("<root><device><serviceList><service>\
+                               <serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>\
+                               <controlURL>/ctl/L3F</controlURL></service><service>\
+                               <serviceType>{}</serviceType>\
+                               <controlURL>/ctl/IPConn</controlURL>\
+                               </service></serviceList></device></root>",
+                              WAN_SERVICES[0]);
+    let response = "<s:Envelope><s:Body><u:GetExternalIPAddressResponse>\
+                    <NewExternalIPAddress>1.2.3.4</NewExternalIPAddress>\
+                    </u:GetExternalIPAddressResponse></s:Body></s:Envelope>"
+                       .to_owned();
+    let addr = http_stand_in(vec![("text/xml", description), ("text/xml", response)]);
+
+    let upnp = Upnp {
+        location: Some(format!("http://{}/rootDesc.xml", addr)),
+        timeout: Duration::from_millis(1000),
+    };
+    assert_eq!(upnp.get(Family::V4).unwrap(), "1.2.3.4".parse::<IpAddr>().unwrap());
+    assert!(upnp.get(Family::V6).is_err());
+}
+
+#[test]
+fn test_natpmp_source() {
+    use std::thread;
+
+    let gateway = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = gateway.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0u8; 2];
+        let (_, client) = gateway.recv_from(&mut buf).unwrap();
+        assert_eq!(buf, [0, 0]);
+        gateway.send_to(&[0, 128, 0, 0, 0, 0, 0, 1, 1, 2, 3, 4], client).unwrap();
+    });
+
+    let natpmp = NatPmp {
+        gateway: addr.to_string(),
+        timeout: Duration::from_millis(1000),
+    };
+    assert_eq!(natpmp.get(Family::V4).unwrap(), "1.2.3.4".parse::<IpAddr>().unwrap());
+
+    assert!(parse_natpmp_response(&[0, 128, 0, 3, 0, 0, 0, 1, 0, 0, 0, 0]).is_err());
+}
+
+#[test]
+fn test_detect() {
+    let sources: Vec<Box<IpSource>> = vec![Box::new(Explicit(None)),
+                                           Box::new(Explicit(Some("2001:db8::1".to_owned()))),
+                                           Box::new(Explicit(Some("1.2.3.4".to_owned())))];
+    assert_eq!(detect(&sources, Family::V4).unwrap(),
+               ("1.2.3.4".parse().unwrap(), "argument".to_owned()));
+    assert_eq!(detect(&sources[..1], Family::V4).unwrap_err().len(), 1);
+    assert!(Explicit(Some("fe80::1".to_owned())).get(Family::V6).is_err());
+}
@@ -2,24 +2,24 @@ extern crate xdg_basedir as xdg;
 extern crate toml;
 extern crate serde;
 extern crate openssl;
+extern crate pb;
+extern crate url;
+extern crate lettre;
+extern crate dbus;
+extern crate hyper;
+extern crate regex;
+extern crate serde_json;
+extern crate yadns;
 
-use serde::Deserialize;
-use std::fs::File;
-use std::io::Read;
+pub mod config;
+pub mod csv;
+pub mod dns;
+pub mod ipsource;
+pub mod notify;
+pub mod state;
+pub mod template;
 
-pub fn load_config<C: Deserialize>(filename: &str) -> Option<C> {
-    xdg::get_config_dirs()
-        .into_iter()
-        .filter_map(|p| File::open(p.join(filename)).ok())
-        .next()
-        .and_then(|mut f| {
-            let mut buf = String::new();
-            match f.read_to_string(&mut buf) {
-                Ok(_) => toml::decode_str(&buf),
-                _ => None,
-            }
-        })
-}
+pub use config::{load_config, load_config_verbose, ConfigError, ConfigLoader};
 
 pub fn permissive_ssl_checker(ctx: &mut openssl::ssl::SslContext) {
     ctx.set_verify(openssl::ssl::SSL_VERIFY_NONE, None);
@@ -0,0 +1,63 @@
+use std::io::{self, Write};
+use super::{Message, Notifier, NotifyError, NotifyResult, Receipt};
+
+/// Prints messages to stdout, or to stderr with syslog priority prefixes
+/// journald understands, for scripts run as systemd services.
+pub struct Console {
+    journal: bool,
+}
+
+impl Console {
+    pub fn stdout() -> Console {
+        Console { journal: false }
+    }
+
+    pub fn journal() -> Console {
+        Console { journal: true }
+    }
+}
+
+fn format_message(msg: &Message, prefix: &str) -> String {
+    let mut text = format!("{}{}\n", prefix, msg.title);
+    for line in msg.body.lines() {
+        text.push_str(&*format!("{}{}\n", prefix, line));
+    }
+    if let Some(ref url) = msg.url {
+        text.push_str(&*format!("{}{}\n", prefix, url));
+    }
+    text
+}
+
+impl Notifier for Console {
+    fn notify(&mut self, msg: &Message) -> NotifyResult {
+        let (backend, result) = if self.journal {
+            // <5> is LOG_NOTICE
+            ("journal", io::stderr().write_all(format_message(msg, "<5>").as_bytes()))
+        } else {
+            ("stdout", io::stdout().write_all(format_message(msg, "").as_bytes()))
+        };
+
+        match result {
+            Ok(_) => {
+                Ok(Receipt {
+                    backend: backend,
+                    id: None,
+                })
+            }
+            Err(err) => {
+                Err(NotifyError::Backend {
+                    backend: backend,
+                    message: err.to_string(),
+                })
+            }
+        }
+    }
+}
+
+#[test]
+fn test_format_message() {
+    assert_eq!(format_message(&Message::note("Title", "line 1\nline 2"), "<5>"),
+               "<5>Title\n<5>line 1\n<5>line 2\n");
+    assert_eq!(format_message(&Message::link("Title", "Body", "http://example.com/"), ""),
+               "Title\nBody\nhttp://example.com/\n");
+}
@@ -0,0 +1,63 @@
+use dbus::{Connection, BusType, MessageItem};
+use dbus::Message as DbusMessage;
+use super::{Message, Notifier, NotifyError, NotifyResult, Receipt, DesktopConfig};
+
+/// Shows messages as desktop notifications,
+/// via `org.freedesktop.Notifications` service on session D-Bus.
+pub struct Desktop {
+    config: DesktopConfig,
+}
+
+impl Desktop {
+    pub fn new(config: DesktopConfig) -> Desktop {
+        Desktop { config: config }
+    }
+}
+
+impl Notifier for Desktop {
+    fn notify(&mut self, msg: &Message) -> NotifyResult {
+        let error = |message: String| {
+            NotifyError::Backend {
+                backend: "desktop",
+                message: message,
+            }
+        };
+
+        let conn = try!(Connection::get_private(BusType::Session).map_err(|err| error(err.to_string())));
+
+        let body = match msg.url {
+            Some(ref url) => format!("{}\n<a href=\"{}\">{}</a>", msg.body, url, url),
+            None => msg.body.clone(),
+        };
+
+        let call = try!(DbusMessage::new_method_call("org.freedesktop.Notifications",
+                                                     "/org/freedesktop/Notifications",
+                                                     "org.freedesktop.Notifications",
+                                                     "Notify")
+                            .map_err(|err| error(err)))
+                       .append_items(&[MessageItem::Str(self.config
+                                                            .app_name
+                                                            .clone()
+                                                            .unwrap_or_else(|| "scripts".to_owned())),
+                                       MessageItem::UInt32(0),
+                                       MessageItem::Str(self.config.icon.clone().unwrap_or_else(String::new)),
+                                       MessageItem::Str(msg.title.clone()),
+                                       MessageItem::Str(body),
+                                       MessageItem::Array(vec![], "s".into()),
+                                       MessageItem::Array(vec![], "{sv}".into()),
+                                       MessageItem::Int32(self.config.timeout.unwrap_or(-1))]);
+
+        let reply = try!(conn.send_with_reply_and_block(call, 2000)
+                             .map_err(|err| error(err.to_string())));
+
+        let id = match reply.get_items().into_iter().next() {
+            Some(MessageItem::UInt32(id)) => Some(id.to_string()),
+            _ => None,
+        };
+
+        Ok(Receipt {
+            backend: "desktop",
+            id: id,
+        })
+    }
+}
@@ -0,0 +1,257 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+use lettre::email::{Email, EmailBuilder};
+use lettre::transport::EmailTransport;
+use lettre::transport::smtp::{SmtpTransportBuilder, SecurityLevel};
+use super::{Message, Notifier, NotifyError, NotifyResult, Receipt, SmtpConfig, SendmailConfig};
+
+/// Splits `Name <addr@host>` into address and name.
+fn parse_address(addr: &str) -> (&str, Option<&str>) {
+    let addr = addr.trim();
+    match (addr.find('<'), addr.ends_with('>')) {
+        (Some(idx), true) => {
+            let name = addr[..idx].trim();
+            (&addr[idx + 1..addr.len() - 1], if name.is_empty() { None } else { Some(name) })
+        }
+        _ => (addr, None),
+    }
+}
+
+fn build_email(from: &str, to: &[String], msg: &Message) -> Result<Email, NotifyError> {
+    let body = match (&msg.email_body, &msg.url) {
+        (&Some(ref body), _) => body.clone(),
+        (&None, &Some(ref url)) => format!("{}\n\n{}", msg.body, url),
+        (&None, &None) => msg.body.clone(),
+    };
+    let subject = msg.email_subject.as_ref().unwrap_or(&msg.title);
+
+    let mut builder = match parse_address(from) {
+        (addr, Some(name)) => EmailBuilder::new().from((addr, name)),
+        (addr, None) => EmailBuilder::new().from(addr),
+    };
+    for addr in to {
+        builder = match parse_address(addr) {
+            (addr, Some(name)) => builder.to((addr, name)),
+            (addr, None) => builder.to(addr),
+        };
+    }
+
+    builder.subject(&**subject)
+           .body(&*body)
+           .build()
+           .map_err(|err| NotifyError::InvalidMessage(err.to_string()))
+}
+
+fn security_level(name: Option<&str>) -> Result<SecurityLevel, NotifyError> {
+    match name.unwrap_or("opportunistic") {
+        "none" => Ok(SecurityLevel::NeverEncrypt),
+        "opportunistic" => Ok(SecurityLevel::Opportunistic),
+        "starttls" => Ok(SecurityLevel::AlwaysEncrypt),
+        "smtps" => Ok(SecurityLevel::EncryptedWrapper),
+        other => {
+            Err(NotifyError::InvalidConfig(format!("unknown SMTP security {}, expected one of \
+                                                    none, opportunistic, starttls or smtps",
+                                                   other)))
+        }
+    }
+}
+
+/// Sends messages as emails via SMTP relay.
+pub struct Smtp {
+    config: SmtpConfig,
+}
+
+impl Smtp {
+    pub fn new(config: SmtpConfig) -> Smtp {
+        Smtp { config: config }
+    }
+}
+
+impl Notifier for Smtp {
+    fn notify(&mut self, msg: &Message) -> NotifyResult {
+        let email = try!(build_email(&*self.config.from, &*self.config.to, msg));
+        let security = try!(security_level(self.config.security.as_ref().map(|s| &**s)));
+
+        let host = self.config.host.as_ref().map(|h| &**h).unwrap_or("localhost");
+        let port = self.config.port.unwrap_or(if let SecurityLevel::EncryptedWrapper = security {
+            465
+        } else {
+            25
+        });
+        let error = |message: String| {
+            NotifyError::Backend {
+                backend: "smtp",
+                message: message,
+            }
+        };
+
+        let mut builder = try!(SmtpTransportBuilder::new((host, port))
+                                   .map_err(|err| error(format!("{}:{}: {}", host, port, err))))
+                              .security_level(security);
+        if let Some(ref hello_name) = self.config.hello_name {
+            builder = builder.hello_name(&**hello_name);
+        }
+        match (&self.config.username, &self.config.password) {
+            (&Some(ref username), &Some(ref password)) => {
+                builder = builder.credentials(&**username, &**password);
+            }
+            (&Some(_), &None) => {
+                return Err(NotifyError::InvalidConfig("SMTP password is missing".to_owned()))
+            }
+            _ => (),
+        }
+
+        let mut mailer = builder.build();
+        let result = mailer.send(email).map_err(|err| error(err.to_string()));
+        mailer.close();
+
+        result.map(|_| {
+            Receipt {
+                backend: "smtp",
+                id: Some(self.config.to.join(", ")),
+            }
+        })
+    }
+}
+
+/// Pipes messages as emails to local `sendmail` command.
+pub struct Sendmail {
+    config: SendmailConfig,
+}
+
+impl Sendmail {
+    pub fn new(config: SendmailConfig) -> Sendmail {
+        Sendmail { config: config }
+    }
+}
+
+impl Notifier for Sendmail {
+    fn notify(&mut self, msg: &Message) -> NotifyResult {
+        let email = try!(build_email(&*self.config.from, &*self.config.to, msg));
+
+        let command = self.config.command.as_ref().map(|c| &**c).unwrap_or("/usr/sbin/sendmail");
+        let error = |message: String| {
+            NotifyError::Backend {
+                backend: "sendmail",
+                message: message,
+            }
+        };
+
+        let mut child = try!(Command::new(command)
+                                 .arg("-i")
+                                 .arg("-f")
+                                 .arg(parse_address(&*self.config.from).0)
+                                 .args(&*self.config
+                                            .to
+                                            .iter()
+                                            .map(|addr| parse_address(addr).0)
+                                            .collect::<Vec<_>>())
+                                 .stdin(Stdio::piped())
+                                 .spawn()
+                                 .map_err(|err| error(format!("{}: {}", command, err))));
+
+        if let Some(ref mut stdin) = child.stdin {
+            try!(stdin.write_all(email.to_string().as_bytes()).map_err(|err| error(err.to_string())));
+        }
+        // close stdin, so sendmail knows the message is over
+        child.stdin.take();
+
+        match child.wait() {
+            Ok(status) if status.success() => {
+                Ok(Receipt {
+                    backend: "sendmail",
+                    id: Some(self.config.to.join(", ")),
+                })
+            }
+            Ok(status) => Err(error(format!("{} exited with {}", command, status))),
+            Err(err) => Err(error(err.to_string())),
+        }
+    }
+}
+
+#[test]
+fn test_build_email() {
+    let email = build_email("from@example.com",
+                            &["to@example.com".to_owned()],
+                            &Message::link("Subject", "Body", "http://example.com/"))
+                    .unwrap()
+                    .to_string();
+    assert!(email.contains("Subject: Subject"));
+    assert!(email.contains("from@example.com"));
+    assert!(email.contains("to@example.com"));
+    assert!(email.contains("Body\r\n\r\nhttp://example.com/") ||
+            email.contains("Body\n\nhttp://example.com/"));
+}
+
+#[test]
+fn test_parse_address() {
+    assert_eq!(parse_address("me@kstep.me"), ("me@kstep.me", None));
+    assert_eq!(parse_address(" Master <me@kstep.me> "), ("me@kstep.me", Some("Master")));
+    assert_eq!(parse_address("<me@kstep.me>"), ("me@kstep.me", None));
+}
+
+#[test]
+fn test_smtp_sink() {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+
+    // minimal SMTP server, which accepts a single message and returns it
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let sink = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut data = String::new();
+        let mut in_data = false;
+
+        writer.write_all(b"220 sink ESMTP\r\n").unwrap();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 {
+                break;
+            }
+
+            if in_data {
+                if line == ".\r\n" {
+                    in_data = false;
+                    writer.write_all(b"250 queued\r\n").unwrap();
+                } else {
+                    data.push_str(&*line);
+                }
+                continue;
+            }
+
+            let command = line.to_uppercase();
+            if command.starts_with("DATA") {
+                in_data = true;
+                writer.write_all(b"354 go ahead\r\n").unwrap();
+            } else if command.starts_with("QUIT") {
+                writer.write_all(b"221 bye\r\n").unwrap();
+                break;
+            } else {
+                writer.write_all(b"250 ok\r\n").unwrap();
+            }
+        }
+        data
+    });
+
+    let mut smtp = Smtp::new(SmtpConfig {
+        host: Some("127.0.0.1".to_owned()),
+        port: Some(port),
+        security: Some("none".to_owned()),
+        username: None,
+        password: None,
+        hello_name: Some("test".to_owned()),
+        from: "Greybook <greybook@example.com>".to_owned(),
+        to: vec!["Master <me@example.com>".to_owned()],
+    });
+    let receipt = smtp.notify(&Message::note("New IP", "1.2.3.4")).unwrap();
+    assert_eq!(receipt.backend, "smtp");
+
+    let data = sink.join().unwrap();
+    assert!(data.contains("Subject: New IP"));
+    assert!(data.contains("me@example.com"));
+    assert!(data.contains("1.2.3.4"));
+}
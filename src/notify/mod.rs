@@ -0,0 +1,530 @@
+//! Notifications sent by scripts to their owner.
+//!
+//! Scripts send messages through a `Chain` of notifiers, declared in
+//! `notify/config.toml`. Notifiers are tried in order until one succeeds:
+//!
+//! ```toml
+//! chain = ["pushbullet", "smtp", "sendmail", "desktop", "journal", "stdout"]
+//!
+//! [smtp]
+//! host = "localhost"        # default
+//! port = 25                 # default
+//! security = "opportunistic"  # default, or "none", "starttls", "smtps"
+//! username = "greybook"     # optional, password is required if set
+//! password = "..."
+//! hello_name = "home.kstep.me"
+//! from = "Greybook <greybook@home.kstep.me>"
+//! to = ["Master <me@kstep.me>", "team@kstep.me"]
+//!
+//! [sendmail]
+//! command = "/usr/sbin/sendmail"  # default
+//! from = "greybook@home.kstep.me"
+//! to = ["me@kstep.me"]
+//!
+//! [desktop]
+//! app_name = "scripts"  # default
+//! icon = "dialog-information"
+//! timeout = 5000        # ms, -1 for server default
+//! ```
+//!
+//! Notifiers which fail to set up are skipped with a warning.
+//! Without the config file messages go to Pushbullet (if it's configured),
+//! falling back to stdout; email needs `[smtp]` or `[sendmail]` settings.
+//!
+//! Message texts are rendered from templates, which scripts provide defaults
+//! for, and which can be overridden per event in `notify/templates.toml`:
+//!
+//! ```toml
+//! [torrent_done]
+//! title = "Загрузка завершена"
+//! body = "{torrent_name} загружен в {torrent_dir}"
+//! email_subject = "..."  # optional, title is used by default
+//! email_body = "..."     # optional, body is used by default
+//! ```
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+use config::{self, ConfigError};
+use template::{self, TemplateError};
+
+pub mod pushbullet;
+pub mod email;
+pub mod desktop;
+pub mod console;
+
+pub use self::pushbullet::Pushbullet;
+pub use self::email::{Smtp, Sendmail};
+pub use self::desktop::Desktop;
+pub use self::console::Console;
+
+include!(concat!(env!("OUT_DIR"), "/notify.rs"));
+
+/// A single notification: a note, or a link if `url` is set.
+/// Email notifiers use `email_subject` and `email_body` if set.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub title: String,
+    pub body: String,
+    pub url: Option<String>,
+    pub email_subject: Option<String>,
+    pub email_body: Option<String>,
+}
+
+impl Message {
+    pub fn note<T: Into<String>, B: Into<String>>(title: T, body: B) -> Message {
+        Message {
+            title: title.into(),
+            body: body.into(),
+            url: None,
+            email_subject: None,
+            email_body: None,
+        }
+    }
+
+    pub fn link<T: Into<String>, B: Into<String>, U: Into<String>>(title: T, body: B, url: U) -> Message {
+        Message {
+            title: title.into(),
+            body: body.into(),
+            url: Some(url.into()),
+            email_subject: None,
+            email_body: None,
+        }
+    }
+}
+
+/// Proof of delivery returned by a notifier.
+#[derive(Debug, Clone)]
+pub struct Receipt {
+    pub backend: &'static str,
+    pub id: Option<String>,
+}
+
+impl fmt::Display for Receipt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.id {
+            Some(ref id) => write!(f, "{} {}", self.backend, id),
+            None => write!(f, "{}", self.backend),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum NotifyError {
+    Config(ConfigError),
+    InvalidMessage(String),
+    InvalidConfig(String),
+    Template(TemplateError),
+    UnknownBackend(String),
+    AllFailed(Vec<NotifyError>),
+    Backend {
+        backend: &'static str,
+        message: String,
+    },
+}
+
+impl NotifyError {
+    /// Exit code for scripts which can't go on without notifications,
+    /// same as for config errors.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            NotifyError::Config(ref err) => err.exit_code(),
+            _ => config::EXIT_CONFIG,
+        }
+    }
+}
+
+impl From<ConfigError> for NotifyError {
+    fn from(err: ConfigError) -> NotifyError {
+        NotifyError::Config(err)
+    }
+}
+
+impl From<TemplateError> for NotifyError {
+    fn from(err: TemplateError) -> NotifyError {
+        NotifyError::Template(err)
+    }
+}
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NotifyError::Config(ref err) => write!(f, "notifier config error: {}", err),
+            NotifyError::InvalidMessage(ref msg) => write!(f, "invalid notification: {}", msg),
+            NotifyError::InvalidConfig(ref msg) => write!(f, "invalid notifier config: {}", msg),
+            NotifyError::Template(ref err) => write!(f, "notification template error: {}", err),
+            NotifyError::UnknownBackend(ref name) => write!(f, "unknown notifier {}", name),
+            NotifyError::AllFailed(ref errors) => {
+                try!(write!(f, "all notifiers failed:"));
+                for err in errors {
+                    try!(write!(f, "\n    {}", err));
+                }
+                Ok(())
+            }
+            NotifyError::Backend { backend, ref message } => {
+                write!(f, "{} notification failed: {}", backend, message)
+            }
+        }
+    }
+}
+
+impl Error for NotifyError {
+    fn description(&self) -> &str {
+        match *self {
+            NotifyError::Config(_) => "notifier config error",
+            NotifyError::InvalidMessage(_) => "invalid notification",
+            NotifyError::InvalidConfig(_) => "invalid notifier config",
+            NotifyError::Template(_) => "notification template error",
+            NotifyError::UnknownBackend(_) => "unknown notifier",
+            NotifyError::AllFailed(_) => "all notifiers failed",
+            NotifyError::Backend { .. } => "notification failed",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            NotifyError::Config(ref err) => Some(err),
+            NotifyError::Template(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+pub type NotifyResult = Result<Receipt, NotifyError>;
+
+impl NotifyConfig {
+    /// Loads `notify/config.toml`, or makes a default config if there's
+    /// no such file: Pushbullet if it's configured, then stdout. SMTP needs
+    /// `[smtp]` settings, so it's never in the default chain.
+    pub fn load() -> Result<NotifyConfig, NotifyError> {
+        match config::load_config::<NotifyConfig>("notify/config.toml") {
+            Ok(config) => Ok(config),
+            Err(ConfigError::NotFound { .. }) => {
+                let mut chain = Vec::new();
+                if Pushbullet::from_config().is_ok() {
+                    chain.push("pushbullet".to_owned());
+                }
+                chain.push("stdout".to_owned());
+
+                Ok(NotifyConfig {
+                    chain: chain,
+                    smtp: None,
+                    sendmail: None,
+                    desktop: None,
+                })
+            }
+            Err(err) => Err(NotifyError::Config(err)),
+        }
+    }
+
+    /// Replaces SMTP settings with script specific ones,
+    /// adding SMTP notifier before console ones if it's not in the chain.
+    pub fn with_smtp(mut self, smtp: SmtpConfig) -> NotifyConfig {
+        self.smtp = Some(smtp);
+        if !self.chain.iter().any(|name| name == "smtp") {
+            let pos = self.chain
+                          .iter()
+                          .position(|name| name == "stdout" || name == "journal")
+                          .unwrap_or(self.chain.len());
+            self.chain.insert(pos, "smtp".to_owned());
+        }
+        self
+    }
+}
+
+/// Message templates for an event, see `template` module for syntax.
+#[derive(Debug, Clone)]
+pub struct Template {
+    pub title: String,
+    pub body: String,
+    pub url: Option<String>,
+    pub email_subject: Option<String>,
+    pub email_body: Option<String>,
+}
+
+impl Template {
+    pub fn new<T: Into<String>, B: Into<String>>(title: T, body: B) -> Template {
+        Template {
+            title: title.into(),
+            body: body.into(),
+            url: None,
+            email_subject: None,
+            email_body: None,
+        }
+    }
+
+    pub fn url<U: Into<String>>(mut self, url: U) -> Template {
+        self.url = Some(url.into());
+        self
+    }
+
+    pub fn email<S: Into<String>, B: Into<String>>(mut self, subject: S, body: B) -> Template {
+        self.email_subject = Some(subject.into());
+        self.email_body = Some(body.into());
+        self
+    }
+
+    /// Overrides templates with ones set for the `event` in `notify/templates.toml`.
+    pub fn load(self, event: &str) -> Result<Template, NotifyError> {
+        match config::load_config::<BTreeMap<String, TemplateConfig>>("notify/templates.toml") {
+            Ok(mut templates) => {
+                Ok(match templates.remove(event) {
+                    Some(config) => self.merge(config),
+                    None => self,
+                })
+            }
+            Err(ConfigError::NotFound { .. }) => Ok(self),
+            Err(err) => Err(NotifyError::Config(err)),
+        }
+    }
+
+    pub fn merge(self, config: TemplateConfig) -> Template {
+        Template {
+            title: config.title.unwrap_or(self.title),
+            body: config.body.unwrap_or(self.body),
+            url: config.url.or(self.url),
+            email_subject: config.email_subject.or(self.email_subject),
+            email_body: config.email_body.or(self.email_body),
+        }
+    }
+
+    pub fn render(&self, vars: &[(&str, &str)]) -> Result<Message, NotifyError> {
+        let render_opt = |tmpl: &Option<String>| -> Result<Option<String>, TemplateError> {
+            match *tmpl {
+                Some(ref tmpl) => template::render(tmpl, vars).map(Some),
+                None => Ok(None),
+            }
+        };
+
+        Ok(Message {
+            title: try!(template::render(&self.title, vars)),
+            body: try!(template::render(&self.body, vars)),
+            url: try!(render_opt(&self.url)),
+            email_subject: try!(render_opt(&self.email_subject)),
+            email_body: try!(render_opt(&self.email_body)),
+        })
+    }
+}
+
+pub trait Notifier {
+    fn notify(&mut self, msg: &Message) -> NotifyResult;
+}
+
+/// Tries notifiers in order until one of them succeeds.
+pub struct Chain {
+    notifiers: Vec<Box<Notifier>>,
+}
+
+impl Chain {
+    pub fn new() -> Chain {
+        Chain { notifiers: Vec::new() }
+    }
+
+    pub fn push<N: Notifier + 'static>(mut self, notifier: N) -> Chain {
+        self.notifiers.push(Box::new(notifier));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.notifiers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notifiers.is_empty()
+    }
+
+    pub fn from_config() -> Result<Chain, NotifyError> {
+        Chain::with_config(&try!(NotifyConfig::load()))
+    }
+
+    /// Builds the chain, skipping notifiers which can't be set up,
+    /// fails only if none is left.
+    pub fn with_config(config: &NotifyConfig) -> Result<Chain, NotifyError> {
+        let mut chain = Chain::new();
+        let mut errors = Vec::new();
+        for name in &config.chain {
+            let notifier = match &**name {
+                "pushbullet" => Pushbullet::from_config().map(|n| Box::new(n) as Box<Notifier>),
+                "smtp" => section(name, &config.smtp).map(|c| Box::new(Smtp::new(c)) as Box<Notifier>),
+                "sendmail" => section(name, &config.sendmail).map(|c| Box::new(Sendmail::new(c)) as Box<Notifier>),
+                "desktop" => {
+                    Ok(Box::new(Desktop::new(config.desktop.clone().unwrap_or(DesktopConfig {
+                        app_name: None,
+                        icon: None,
+                        timeout: None,
+                    }))) as Box<Notifier>)
+                }
+                "journal" => Ok(Box::new(Console::journal()) as Box<Notifier>),
+                "stdout" => Ok(Box::new(Console::stdout()) as Box<Notifier>),
+                _ => Err(NotifyError::UnknownBackend(name.clone())),
+            };
+            match notifier {
+                Ok(notifier) => chain.notifiers.push(notifier),
+                Err(err) => {
+                    let _ = writeln!(io::stderr(), "skipping {} notifier: {}", name, err);
+                    errors.push(err);
+                }
+            }
+        }
+
+        if !chain.is_empty() {
+            Ok(chain)
+        } else if errors.is_empty() {
+            Err(NotifyError::InvalidConfig("notifier chain is empty".to_owned()))
+        } else {
+            Err(NotifyError::AllFailed(errors))
+        }
+    }
+}
+
+impl Default for Chain {
+    fn default() -> Chain {
+        Chain::new()
+    }
+}
+
+/// Sends the message through the chain from `notify/config.toml`.
+pub fn send(msg: &Message) -> NotifyResult {
+    try!(Chain::from_config()).notify(msg)
+}
+
+/// Renders the event `template` with `vars` and sends it
+/// through the chain from `notify/config.toml`.
+pub fn send_event(event: &str, template: Template, vars: &[(&str, &str)]) -> NotifyResult {
+    notify_event(&mut try!(Chain::from_config()), event, template, vars)
+}
+
+/// Renders the event `template` with `vars` and sends it with `notifier`.
+pub fn notify_event<N: Notifier>(notifier: &mut N,
+                                 event: &str,
+                                 template: Template,
+                                 vars: &[(&str, &str)])
+                                 -> NotifyResult {
+    let msg = try!(try!(template.load(event)).render(vars));
+    notifier.notify(&msg)
+}
+
+fn section<T: Clone>(name: &str, section: &Option<T>) -> Result<T, NotifyError> {
+    section.clone().ok_or_else(|| {
+        NotifyError::InvalidConfig(format!("[{}] section is missing in notify/config.toml", name))
+    })
+}
+
+impl Notifier for Chain {
+    fn notify(&mut self, msg: &Message) -> NotifyResult {
+        let mut errors = Vec::new();
+        for notifier in self.notifiers.iter_mut() {
+            match notifier.notify(msg) {
+                Ok(receipt) => return Ok(receipt),
+                Err(err) => errors.push(err),
+            }
+        }
+        Err(NotifyError::AllFailed(errors))
+    }
+}
+
+#[cfg(test)]
+struct Failing;
+
+#[cfg(test)]
+impl Notifier for Failing {
+    fn notify(&mut self, _msg: &Message) -> NotifyResult {
+        Err(NotifyError::Backend {
+            backend: "failing",
+            message: "always fails".to_owned(),
+        })
+    }
+}
+
+#[test]
+fn test_chain_fallback() {
+    let msg = Message::note("Title", "Body");
+
+    let mut chain = Chain::new().push(Failing).push(Console::stdout()).push(Failing);
+    assert_eq!(chain.notify(&msg).unwrap().backend, "stdout");
+
+    let mut chain = Chain::new().push(Failing).push(Failing);
+    assert!(match chain.notify(&msg) {
+        Err(NotifyError::AllFailed(ref errors)) => errors.len() == 2,
+        _ => false,
+    });
+}
+
+#[test]
+fn test_chain_with_config() {
+    let config = NotifyConfig {
+        chain: vec!["journal".to_owned(), "stdout".to_owned()],
+        smtp: None,
+        sendmail: None,
+        desktop: None,
+    };
+    assert_eq!(Chain::with_config(&config).unwrap().len(), 2);
+
+    // misconfigured notifiers are skipped
+    let config = NotifyConfig { chain: vec!["smtp".to_owned(), "stdout".to_owned()], ..config };
+    assert_eq!(Chain::with_config(&config).unwrap().len(), 1);
+
+    let config = NotifyConfig { chain: vec!["smtp".to_owned(), "carrier-pigeon".to_owned()], ..config };
+    assert!(match Chain::with_config(&config) {
+        Err(NotifyError::AllFailed(ref errors)) => {
+            errors.len() == 2 &&
+            match errors[1] {
+                NotifyError::UnknownBackend(ref name) => name == "carrier-pigeon",
+                _ => false,
+            }
+        }
+        _ => false,
+    });
+
+    let config = NotifyConfig { chain: vec![], ..config };
+    assert!(Chain::with_config(&config).is_err());
+}
+
+#[test]
+fn test_template_render() {
+    let template = Template::new("New IP", "{ip}")
+                       .email("New IP address", "Was {old_ip}, now {ip}.")
+                       .merge(TemplateConfig {
+                           title: Some("Новый IP".to_owned()),
+                           body: None,
+                           url: None,
+                           email_subject: None,
+                           email_body: None,
+                       });
+
+    let msg = template.render(&[("ip", "1.2.3.4"), ("old_ip", "4.3.2.1")]).unwrap();
+    assert_eq!(msg.title, "Новый IP");
+    assert_eq!(msg.body, "1.2.3.4");
+    assert_eq!(msg.url, None);
+    assert_eq!(msg.email_subject, Some("New IP address".to_owned()));
+    assert_eq!(msg.email_body, Some("Was 4.3.2.1, now 1.2.3.4.".to_owned()));
+
+    assert!(template.render(&[("ip", "1.2.3.4")]).is_err());
+}
+
+#[test]
+fn test_notify_config_with_smtp() {
+    let smtp = SmtpConfig {
+        host: None,
+        port: None,
+        security: None,
+        username: None,
+        password: None,
+        hello_name: None,
+        from: "from@example.com".to_owned(),
+        to: vec!["to@example.com".to_owned()],
+    };
+    let config = NotifyConfig {
+        chain: vec!["pushbullet".to_owned(), "journal".to_owned()],
+        smtp: None,
+        sendmail: None,
+        desktop: None,
+    };
+
+    let config = config.with_smtp(smtp.clone());
+    assert_eq!(config.chain, vec!["pushbullet", "smtp", "journal"]);
+
+    let config = config.with_smtp(smtp);
+    assert_eq!(config.chain, vec!["pushbullet", "smtp", "journal"]);
+}
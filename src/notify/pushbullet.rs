@@ -0,0 +1,174 @@
+use pb::{PbAPI, PushMsg, TargetIden, Push, PushData};
+use url::Url;
+use config::load_config;
+use super::{Message, Notifier, NotifyError, NotifyResult, Receipt};
+
+include!(concat!(env!("OUT_DIR"), "/notify-pushbullet.rs"));
+
+/// Pushbullet notifier, configured with `pushbullet/config.toml`:
+///
+/// ```toml
+/// access_token = "..."
+/// device_iden = "..."      # optional, source device
+/// target_device = "..."    # optional, at most one of target_* keys,
+/// target_channel = "..."   # pushes go to all user's devices
+/// target_email = "..."     # if none of them is set
+/// ```
+pub struct Pushbullet {
+    api: PbAPI,
+    target: Target,
+    device_iden: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    User,
+    Device(String),
+    Channel(String),
+    Email(String),
+}
+
+impl Target {
+    fn iden(&self) -> TargetIden {
+        match *self {
+            Target::User => TargetIden::CurrentUser,
+            Target::Device(ref iden) => TargetIden::DeviceIden(iden.clone()),
+            Target::Channel(ref tag) => TargetIden::ChannelTag(tag.clone()),
+            Target::Email(ref email) => TargetIden::Email(email.clone()),
+        }
+    }
+}
+
+impl Pushbullet {
+    pub fn new(config: PbConfig) -> Result<Pushbullet, NotifyError> {
+        let target = try!(config_target(&config));
+        Ok(Pushbullet {
+            api: PbAPI::new(&*config.access_token),
+            target: target,
+            device_iden: config.device_iden,
+        })
+    }
+
+    pub fn from_config() -> Result<Pushbullet, NotifyError> {
+        Pushbullet::new(try!(load_config("pushbullet/config.toml")))
+    }
+
+    /// Overrides push target set in config.
+    pub fn target(mut self, target: Target) -> Pushbullet {
+        self.target = target;
+        self
+    }
+
+    fn push_msg(&self, msg: &Message) -> Result<PushMsg, NotifyError> {
+        build_push(msg, &self.target, self.device_iden.clone())
+    }
+}
+
+impl Notifier for Pushbullet {
+    fn notify(&mut self, msg: &Message) -> NotifyResult {
+        let push = try!(self.push_msg(msg));
+        match self.api.send(&push) {
+            Ok(Push { iden, .. }) => {
+                Ok(Receipt {
+                    backend: "pushbullet",
+                    id: Some(iden),
+                })
+            }
+            Err(err) => {
+                Err(NotifyError::Backend {
+                    backend: "pushbullet",
+                    message: err.to_string(),
+                })
+            }
+        }
+    }
+}
+
+fn config_target(config: &PbConfig) -> Result<Target, NotifyError> {
+    match (&config.target_device, &config.target_channel, &config.target_email) {
+        (&None, &None, &None) => Ok(Target::User),
+        (&Some(ref device), &None, &None) => Ok(Target::Device(device.clone())),
+        (&None, &Some(ref channel), &None) => Ok(Target::Channel(channel.clone())),
+        (&None, &None, &Some(ref email)) => Ok(Target::Email(email.clone())),
+        _ => {
+            Err(NotifyError::InvalidConfig("only one of target_device, target_channel and \
+                                            target_email can be set"
+                                               .to_owned()))
+        }
+    }
+}
+
+fn build_push(msg: &Message, target: &Target, device_iden: Option<String>) -> Result<PushMsg, NotifyError> {
+    let data = match msg.url {
+        Some(ref url) => {
+            PushData::Link(Some(try!(Url::parse(url).map_err(|err| {
+                NotifyError::InvalidMessage(format!("bad link {}: {}", url, err))
+            }))))
+        }
+        None => PushData::Note,
+    };
+
+    Ok(PushMsg {
+        title: Some(msg.title.clone().into()),
+        body: Some(msg.body.clone().into()),
+        target: target.iden(),
+        data: data,
+        source_device_iden: device_iden,
+    })
+}
+
+#[cfg(test)]
+fn test_config() -> PbConfig {
+    PbConfig {
+        access_token: "token".to_owned(),
+        device_iden: Some("source".to_owned()),
+        target_device: None,
+        target_channel: None,
+        target_email: None,
+    }
+}
+
+#[test]
+fn test_target() {
+    let mut config = test_config();
+    assert_eq!(config_target(&config).ok(), Some(Target::User));
+
+    config.target_channel = Some("news".to_owned());
+    assert_eq!(config_target(&config).ok(), Some(Target::Channel("news".to_owned())));
+
+    config.target_email = Some("me@example.com".to_owned());
+    assert!(config_target(&config).is_err());
+}
+
+#[test]
+fn test_build_push() {
+    let push = build_push(&Message::note("Title", "Body"),
+                          &Target::User,
+                          Some("source".to_owned()))
+                   .unwrap();
+    assert_eq!(push.title.as_ref().map(|s| &**s), Some("Title"));
+    assert_eq!(push.body.as_ref().map(|s| &**s), Some("Body"));
+    assert_eq!(push.source_device_iden, Some("source".to_owned()));
+    assert!(match push.target {
+        TargetIden::CurrentUser => true,
+        _ => false,
+    });
+    assert!(match push.data {
+        PushData::Note => true,
+        _ => false,
+    });
+
+    let push = build_push(&Message::link("Title", "Body", "http://example.com/"),
+                          &Target::Email("me@example.com".to_owned()),
+                          None)
+                   .unwrap();
+    assert!(match push.data {
+        PushData::Link(Some(ref url)) => url.serialize() == "http://example.com/",
+        _ => false,
+    });
+
+    assert!(build_push(&Message::link("Title", "Body", "not a url"),
+                       &Target::User,
+                       None)
+                .is_err());
+}
@@ -0,0 +1,44 @@
+//! Small state files kept between script runs under XDG data dir,
+//! e.g. `~/.local/share/yadns/last_ip`.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use xdg;
+
+/// Path to the state file, parent dirs are created if needed.
+pub fn state_path(filename: &str) -> io::Result<PathBuf> {
+    let path = try!(xdg::get_data_home().map_err(|_| {
+        io::Error::new(io::ErrorKind::NotFound, "can't find XDG data home dir")
+    }))
+                   .join(filename);
+    if let Some(parent) = path.parent() {
+        try!(fs::create_dir_all(parent));
+    }
+    Ok(path)
+}
+
+/// Reads the whole state file, `None` if it doesn't exist or can't be read.
+pub fn read_state(filename: &str) -> Option<String> {
+    state_path(filename)
+        .and_then(|path| File::open(path))
+        .and_then(|mut f| {
+            let mut buf = String::new();
+            f.read_to_string(&mut buf).map(|_| buf)
+        })
+        .ok()
+}
+
+/// Replaces the state file contents.
+pub fn write_state(filename: &str, contents: &str) -> io::Result<()> {
+    state_path(filename)
+        .and_then(|path| File::create(path))
+        .and_then(|mut f| f.write_all(contents.as_bytes()))
+}
+
+/// Appends a line to the state file, creating it if needed.
+pub fn append_state(filename: &str, line: &str) -> io::Result<()> {
+    state_path(filename)
+        .and_then(|path| OpenOptions::new().append(true).create(true).open(path))
+        .and_then(|mut f| writeln!(f, "{}", line))
+}
@@ -0,0 +1,85 @@
+//! Tiny template engine for user-facing messages.
+//!
+//! Placeholders look like `{name}` and are replaced with variable values,
+//! use `{{` and `}}` to get literal braces.
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum TemplateError {
+    Unclosed(usize),
+    Unopened(usize),
+    Unknown(String),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TemplateError::Unclosed(pos) => write!(f, "unclosed placeholder at {}", pos),
+            TemplateError::Unopened(pos) => write!(f, "unexpected }} at {}", pos),
+            TemplateError::Unknown(ref name) => write!(f, "unknown placeholder {{{}}}", name),
+        }
+    }
+}
+
+impl Error for TemplateError {
+    fn description(&self) -> &str {
+        match *self {
+            TemplateError::Unclosed(_) => "unclosed placeholder",
+            TemplateError::Unopened(_) => "unexpected closing brace",
+            TemplateError::Unknown(_) => "unknown placeholder",
+        }
+    }
+}
+
+/// Renders `template` replacing placeholders with values from `vars`.
+pub fn render(template: &str, vars: &[(&str, &str)]) -> Result<String, TemplateError> {
+    let mut result = String::with_capacity(template.len());
+    let mut chars = template.char_indices().peekable();
+
+    while let Some((pos, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, c)| c);
+        match c {
+            '{' if next == Some('{') => {
+                chars.next();
+                result.push('{');
+            }
+            '}' if next == Some('}') => {
+                chars.next();
+                result.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '}')) => break,
+                        Some((_, c)) => name.push(c),
+                        None => return Err(TemplateError::Unclosed(pos)),
+                    }
+                }
+
+                match vars.iter().find(|&&(key, _)| key == &*name) {
+                    Some(&(_, value)) => result.push_str(value),
+                    None => return Err(TemplateError::Unknown(name)),
+                }
+            }
+            '}' => return Err(TemplateError::Unopened(pos)),
+            c => result.push(c),
+        }
+    }
+
+    Ok(result)
+}
+
+#[test]
+fn test_render() {
+    let vars = [("ip", "1.2.3.4"), ("old_ip", "4.3.2.1")];
+    assert_eq!(render("New IP: {ip} (was {old_ip})", &vars),
+               Ok("New IP: 1.2.3.4 (was 4.3.2.1)".to_owned()));
+    assert_eq!(render("{{ip}} = {ip}", &vars), Ok("{ip} = 1.2.3.4".to_owned()));
+    assert_eq!(render("Привет, {ip}!", &vars), Ok("Привет, 1.2.3.4!".to_owned()));
+    assert_eq!(render("{ip", &vars), Err(TemplateError::Unclosed(0)));
+    assert_eq!(render("ip}", &vars), Err(TemplateError::Unopened(2)));
+    assert_eq!(render("{name}", &vars), Err(TemplateError::Unknown("name".to_owned())));
+}
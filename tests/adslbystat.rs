@@ -0,0 +1,335 @@
+//! Runs adslbystat against a local server which serves test pages
+//! from `tests/fixtures/adslby` instead of the ISP site.
+
+extern crate hyper;
+extern crate serde_json;
+extern crate time;
+
+use hyper::header::{Authorization, Basic};
+use hyper::server::{Handler, Listening, Request, Response, Server};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+use serde_json::Value;
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+const CREDS: &'static str = "username = \"user\"\npassword = \"secret\"\n";
+
+fn fixture(name: &str) -> Vec<u8> {
+    let mut buf = Vec::new();
+    File::open(format!("{}/tests/fixtures/adslby/{}", env!("CARGO_MANIFEST_DIR"), name))
+        .and_then(|mut f| f.read_to_end(&mut buf))
+        .unwrap();
+    buf
+}
+
+struct Pages {
+    account: Vec<u8>,
+    traffic: Vec<u8>,
+    credit: Option<Vec<u8>>,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl Handler for Pages {
+    fn handle(&self, req: Request, mut res: Response) {
+        let path = match req.uri {
+            RequestUri::AbsolutePath(ref path) => path.clone(),
+            _ => String::new(),
+        };
+        self.requests.lock().unwrap().push(path.clone());
+
+        let authorized = match req.headers.get::<Authorization<Basic>>() {
+            Some(&Authorization(ref basic)) => {
+                basic.username == "user" && basic.password.as_ref().map(|p| &**p) == Some("secret")
+            }
+            None => false,
+        };
+        let body = if !authorized {
+            *res.status_mut() = StatusCode::Unauthorized;
+            &b"Authorization required"[..]
+        } else {
+            match &*path {
+                "/001.htm" => &*self.account,
+                "/002.htm" => &*self.traffic,
+                "/credit.js?credit=on" => match self.credit {
+                    Some(ref credit) => &**credit,
+                    None => {
+                        *res.status_mut() = StatusCode::Unauthorized;
+                        &b"Authorization required"[..]
+                    }
+                },
+                _ => {
+                    *res.status_mut() = StatusCode::NotFound;
+                    &b"Not found"[..]
+                }
+            }
+        };
+        res.send(body).unwrap();
+    }
+}
+
+/// Fake ISP site serving `account` page as account stats and `credit`
+/// as the credit request response to `user:secret`.
+///
+/// Every test starts its own server on a random port. Dropping the site
+/// closes the listening socket, but hyper keeps its worker threads until
+/// the test binary exits, so a closed site may still answer for a while.
+struct Site {
+    listening: Listening,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl Site {
+    fn new(account: &str, credit: &str) -> Site {
+        Site::start(account, Some(fixture(credit)))
+    }
+
+    /// Site which rejects credit requests with 401.
+    fn without_credit(account: &str) -> Site {
+        Site::start(account, None)
+    }
+
+    fn start(account: &str, credit: Option<Vec<u8>>) -> Site {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let pages = Pages {
+            account: fixture(account),
+            traffic: fixture("traffic.htm"),
+            credit: credit,
+            requests: requests.clone(),
+        };
+        Site {
+            listening: Server::http("127.0.0.1:0").unwrap().handle(pages).unwrap(),
+            requests: requests,
+        }
+    }
+
+    fn url(&self) -> String {
+        format!("http://{}", self.listening.socket)
+    }
+
+    fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for Site {
+    fn drop(&mut self) {
+        let _ = self.listening.close();
+    }
+}
+
+struct Run {
+    code: i32,
+    stdout: String,
+    stderr: String,
+    root: PathBuf,
+}
+
+impl Run {
+    /// The output as a JSON document, all other messages go to stderr.
+    fn json(&self) -> Value {
+        serde_json::from_str(&*self.stdout).unwrap()
+    }
+
+    fn state(&self, name: &str) -> Option<String> {
+        let mut buf = String::new();
+        File::open(self.root.join("data/adslby").join(name))
+            .and_then(|mut f| f.read_to_string(&mut buf))
+            .ok()
+            .map(|_| buf)
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+fn binary() -> PathBuf {
+    let mut dir = env::current_exe().unwrap();
+    dir.pop();
+    if dir.ends_with("deps") {
+        dir.pop();
+    }
+    dir.join("adslbystat")
+}
+
+static RUNS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Runs adslbystat with `config` pointed to the `site`, isolated from
+/// user config and state with its own XDG dirs.
+fn adslbystat(site: &Site, config: &str, args: &[&str]) -> Run {
+    let root = env::temp_dir().join(format!("adslbystat-test-{}-{}",
+                                            time::precise_time_ns(),
+                                            RUNS.fetch_add(1, Ordering::SeqCst)));
+    let config_dir = root.join("config");
+    fs::create_dir_all(config_dir.join("adslby")).unwrap();
+    File::create(config_dir.join("adslby/creds.toml"))
+        .and_then(|mut f| write!(f, "base_url = \"{}\"\n{}", site.url(), config))
+        .unwrap();
+
+    let output = Command::new(binary())
+                     .args(args)
+                     .env("XDG_CONFIG_HOME", &config_dir)
+                     .env("XDG_CONFIG_DIRS", root.join("etc"))
+                     .env("XDG_DATA_HOME", root.join("data"))
+                     .output()
+                     .unwrap();
+    Run {
+        code: output.status.code().unwrap(),
+        stdout: String::from_utf8(output.stdout).unwrap(),
+        stderr: String::from_utf8(output.stderr).unwrap(),
+        root: root,
+    }
+}
+
+fn approx(value: Option<&Value>, expected: f64) -> bool {
+    value.and_then(Value::as_f64).map(|value| (value - expected).abs() < 0.01).unwrap_or(false)
+}
+
+#[test]
+fn test_enabled() {
+    let site = Site::new("enabled.htm", "credit_on.js");
+    let run = adslbystat(&site, CREDS, &["--format", "json"]);
+    assert_eq!(run.code, 0);
+
+    let acct = run.json();
+    assert_eq!(acct.lookup("enabled").and_then(Value::as_bool), Some(true));
+    assert!(approx(acct.lookup("account"), 12345.67));
+    assert_eq!(acct.lookup("days").and_then(Value::as_i64), Some(25));
+    assert_eq!(acct.lookup("price").and_then(Value::as_i64), Some(2));
+    assert!(acct.lookup("credit").map(Value::is_null).unwrap_or(true));
+
+    assert_eq!(site.requests(), vec!["/001.htm"]);
+    assert!(run.state("history").unwrap().contains("\t25\t2\t\t1"));
+}
+
+#[test]
+fn test_disabled() {
+    let site = Site::new("disabled.htm", "credit_on.js");
+    let run = adslbystat(&site, CREDS, &["--format", "json"]);
+    assert_eq!(run.code, 1);
+    assert_eq!(run.json().lookup("enabled").and_then(Value::as_bool), Some(false));
+    assert_eq!(site.requests(), vec!["/001.htm"]);
+}
+
+#[test]
+fn test_negative_balance() {
+    let site = Site::new("negative.htm", "credit_on.js");
+    let run = adslbystat(&site, CREDS, &["--format", "json"]);
+    assert_eq!(run.code, 1);
+
+    let acct = run.json();
+    assert!(approx(acct.lookup("account"), -15.3));
+    assert_eq!(acct.lookup("days").and_then(Value::as_i64), Some(-3));
+    assert_eq!(acct.lookup("credit").and_then(Value::as_i64), Some(20));
+}
+
+#[test]
+fn test_credit_on() {
+    let site = Site::new("negative.htm", "credit_on.js");
+    let run = adslbystat(&site, CREDS, &["credit"]);
+    assert_eq!(run.code, 0);
+    assert!(run.stderr.contains("Credit requested because account is disabled: credit was enabled"));
+    assert_eq!(site.requests(), vec!["/001.htm", "/credit.js?credit=on"]);
+    assert!(run.state("credit_enabled").is_some());
+}
+
+#[test]
+fn test_credit_off() {
+    let site = Site::new("negative.htm", "credit_off.js");
+    let run = adslbystat(&site, CREDS, &["credit"]);
+    assert_eq!(run.code, 1);
+    assert!(run.stderr.contains("credit was not enabled, account status: Выключен"));
+    assert!(run.state("credit_enabled").is_none());
+}
+
+#[test]
+fn test_credit_unauthorized() {
+    let site = Site::without_credit("negative.htm");
+    let run = adslbystat(&site, CREDS, &["credit"]);
+    assert_eq!(run.code, 2);
+    assert!(run.stderr.contains("Error enabling credit"));
+    assert!(run.stderr.contains("HTTP status 401"));
+    assert_eq!(site.requests(), vec!["/001.htm", "/credit.js?credit=on"]);
+    assert!(run.state("credit_enabled").is_none());
+}
+
+#[test]
+fn test_auto_credit() {
+    let site = Site::new("credit.htm", "credit_on.js");
+    let run = adslbystat(&site, &*format!("{}[credit]\nauto = true\nmax_percent = 50\n", CREDS), &[]);
+    assert_eq!(run.code, 0);
+    assert!(run.stderr.contains("Credit requested because balance is negative"));
+    assert_eq!(site.requests(), vec!["/001.htm", "/credit.js?credit=on"]);
+
+    let site = Site::new("credit.htm", "credit_on.js");
+    let run = adslbystat(&site, &*format!("{}[credit]\nauto = true\nmax_percent = 30\n", CREDS), &[]);
+    assert_eq!(run.code, 0);
+    assert!(run.stderr.contains("allowed credit 50% exceeds the 30% limit"));
+    assert_eq!(site.requests(), vec!["/001.htm"]);
+}
+
+#[test]
+fn test_unauthorized() {
+    let site = Site::new("enabled.htm", "credit_on.js");
+    let run = adslbystat(&site, "username = \"user\"\npassword = \"wrong\"\n", &[]);
+    assert_eq!(run.code, 2);
+    assert!(run.stderr.contains("HTTP status 401"));
+    assert!(run.state("history").is_none());
+}
+
+#[test]
+fn test_accounts() {
+    let site = Site::new("enabled.htm", "credit_on.js");
+    let accounts = "[[account]]\nname = \"home\"\nusername = \"user\"\npassword = \"secret\"\n\n\
+                    [[account]]\nname = \"office\"\nusername = \"user\"\npassword = \"wrong\"\n";
+
+    let run = adslbystat(&site, accounts, &["--format", "json"]);
+    assert_eq!(run.code, 2);
+    assert!(run.stderr.contains("office: Error requesting account stats: HTTP status 401"));
+    assert_eq!(run.json().lookup("home.days").and_then(Value::as_i64), Some(25));
+    assert!(run.state("history-home").is_some());
+
+    let run = adslbystat(&site, accounts, &["--account", "home"]);
+    assert_eq!(run.code, 0);
+
+    let run = adslbystat(&site, accounts, &["--account", "nowhere"]);
+    assert_eq!(run.code, 64);
+}
+
+#[test]
+fn test_account_named_as_subcommand() {
+    let site = Site::new("negative.htm", "credit_on.js");
+    let accounts = "[[account]]\nname = \"credit\"\nusername = \"user\"\npassword = \"secret\"\n\n\
+                    [[account]]\nname = \"traffic\"\nusername = \"user\"\npassword = \"secret\"\n";
+
+    let run = adslbystat(&site, accounts, &["--account", "credit"]);
+    assert_eq!(run.code, 1);
+    assert_eq!(site.requests(), vec!["/001.htm"]);
+
+    let run = adslbystat(&site, accounts, &["--account", "traffic", "--format", "json"]);
+    assert_eq!(run.code, 1);
+    assert_eq!(run.json().lookup("traffic.days").and_then(Value::as_i64), Some(-3));
+    assert_eq!(site.requests(), vec!["/001.htm", "/001.htm"]);
+}
+
+#[test]
+fn test_traffic() {
+    let site = Site::new("enabled.htm", "credit_on.js");
+    let run = adslbystat(&site, CREDS, &["traffic", "--daily", "--format", "json"]);
+    assert_eq!(run.code, 0);
+    assert_eq!(site.requests(), vec!["/002.htm"]);
+
+    let days = run.json();
+    let days = days.as_array().unwrap();
+    assert_eq!(days.len(), 2);
+    assert_eq!(days[0].lookup("date").and_then(Value::as_string), Some("2016-05-24"));
+    assert!(approx(days[0].lookup("cost"), 3.0));
+}
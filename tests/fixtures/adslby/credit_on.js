@@ -0,0 +1 @@
+credit_result({stat: ''});
@@ -0,0 +1,60 @@
+//! Runs yaddns subcommands which don't need the DNS API.
+
+extern crate serde_json;
+extern crate time;
+
+use serde_json::Value;
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+const HISTORY: &'static str = "2016-05-24T10:00:00Z\thome.example.com\tA\t10.0.0.1\t10.0.0.2\tdns\n\
+                               2016-05-25T10:00:00Z\thome.example.com\tAAAA\t\t2001:db8::1\tlocal\n";
+
+fn binary() -> PathBuf {
+    let mut dir = env::current_exe().unwrap();
+    dir.pop();
+    if dir.ends_with("deps") {
+        dir.pop();
+    }
+    dir.join("yaddns")
+}
+
+/// Runs yaddns with no config at all and only the history state file.
+fn yaddns(args: &[&str]) -> Output {
+    let root = env::temp_dir().join(format!("yaddns-test-{}", time::precise_time_ns()));
+    fs::create_dir_all(root.join("data/yadns")).unwrap();
+    File::create(root.join("data/yadns/history"))
+        .and_then(|mut f| f.write_all(HISTORY.as_bytes()))
+        .unwrap();
+
+    let output = Command::new(binary())
+                     .args(args)
+                     .env("XDG_CONFIG_HOME", root.join("config"))
+                     .env("XDG_CONFIG_DIRS", root.join("etc"))
+                     .env("XDG_DATA_HOME", root.join("data"))
+                     .output()
+                     .unwrap();
+    let _ = fs::remove_dir_all(&root);
+    output
+}
+
+#[test]
+fn test_history_without_config() {
+    let output = yaddns(&["history"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("home.example.com A: 10.0.0.1 -> 10.0.0.2 (via dns)"));
+    assert!(stdout.contains("home.example.com AAAA: none -> 2001:db8::1 (via local)"));
+
+    let output = yaddns(&["history", "--csv"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().starts_with("timestamp,record,type,old,new,source\n"));
+
+    let output = yaddns(&["history", "--json"]);
+    assert!(output.status.success());
+    let entries: Value = serde_json::from_str(&*String::from_utf8(output.stdout).unwrap()).unwrap();
+    assert_eq!(entries.as_array().map(Vec::len), Some(2));
+}